@@ -1,159 +1,710 @@
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
 
-use log::debug;
+use indexmap::IndexMap;
+use log::{debug, warn};
+
+use crate::error::OpdayError;
 use toml::Table;
 
-use serde::{Deserialize, Serialize};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::{schema_for, JsonSchema};
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_yaml::Mapping;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DockerComposeFormat {
-    pub version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
     pub services: Mapping,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Scope {
-    pub hosts: Vec<String>,
+    pub name: String,
+    pub hosts: Vec<Host>,
     pub export_path: String,
     pub registry: String,
     pub registry_auth_config: String,
     pub registry_export_auth_config: String,
     pub docker_compose_overrides: Vec<String>,
     pub ssh_private_key: Option<String>,
+    pub ssh_user: Option<String>,
+    pub ssh_port: Option<u16>,
+    pub ssh_jump_host: Option<String>,
+    pub ssh_known_hosts: Option<KnownHosts>,
+    pub ssh_connect_timeout: Option<u64>,
+    pub ssh_keepalive_interval: Option<u64>,
+    pub ssh_multiplex: bool,
+    pub command_timeout: Option<u64>,
+    pub retries: u32,
+    pub retry_delay: u64,
+    pub build_arg: BTreeMap<String, String>,
+    pub sync_files: Vec<String>,
+    pub hooks: Vec<Hook>,
+    pub placement: BTreeMap<String, Vec<String>>,
+}
+
+impl Scope {
+    pub fn addresses(&self) -> Vec<&str> {
+        self.hosts
+            .iter()
+            .map(|host| host.address.as_str())
+            .collect()
+    }
+
+    /// Settings for one host: its own values override ones of environment.
+    pub fn for_host(&self, host: &Host) -> Scope {
+        let mut scope = self.clone();
+        scope.hosts = vec![host.clone()];
+        if let Some(user) = &host.user {
+            scope.ssh_user = Some(user.clone());
+        }
+        if let Some(port) = host.port {
+            scope.ssh_port = Some(port);
+        }
+        if let Some(ssh_private_key) = &host.ssh_private_key {
+            scope.ssh_private_key = Some(ssh_private_key.clone());
+        }
+        if let Some(export_path) = &host.export_path {
+            scope.export_path = export_path.clone();
+        }
+        if let Some(docker_compose_overrides) = &host.docker_compose_overrides {
+            scope.docker_compose_overrides = docker_compose_overrides.clone();
+        }
+        scope
+    }
+
+    /// Services of `services` placed on `host` by its roles.
+    /// Services not mentioned in `placement` run on every host.
+    pub fn placed_services<'a>(&self, host: &Host, services: &[&'a str]) -> Vec<&'a str> {
+        services
+            .iter()
+            .copied()
+            .filter(|service| {
+                let mut roles = self
+                    .placement
+                    .iter()
+                    .filter(|(_, placed)| placed.iter().any(|placed| placed == service))
+                    .map(|(role, _)| role)
+                    .peekable();
+                roles.peek().is_none() || roles.any(|role| host.roles.contains(role))
+            })
+            .collect()
+    }
+
+    /// Checks that `placement` mentions only services from compose file.
+    pub fn check_placement(&self, services: &[&str]) -> Result<(), String> {
+        for (role, placed) in &self.placement {
+            if let Some(unknown) = placed.iter().find(|s| !services.contains(&s.as_str())) {
+                return Err(format!(
+                    "Service `{}` placed on role `{}` is not found in compose file. Known services: {}.",
+                    unknown,
+                    role,
+                    services.join(", ")
+                ));
+            }
+            if !self.hosts.iter().any(|host| host.roles.contains(role)) {
+                warn!(
+                    "No hosts with role `{}` in `{}` environment, services {} are not deployed.",
+                    role,
+                    self.name,
+                    placed.join(", ")
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Environment build args followed by `extra` ones, so later values win.
+    pub fn build_args_with(&self, extra: &[String]) -> Vec<String> {
+        self.build_arg
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .chain(extra.iter().cloned())
+            .collect()
+    }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Configuration {
     pub path: String,
     pub docker_compose_file: String,
     pub environments: Vec<Scope>,
+    /// Sections of providers by provider name
+    pub providers: BTreeMap<String, toml::Value>,
 }
 
-fn get_string_value<'a>(
-    current: &'a Table,
-    base: &'a Table,
-    key: &str,
-    required: bool,
-) -> Option<String> {
-    if current.contains_key(key) {
-        return Some(current[key].as_str().unwrap().to_string());
-    } else if base.contains_key(key) {
-        return Some(base[key].as_str().unwrap().to_string());
-    } else if required {
-        panic!("Can't find config value for `{}` key.", key);
-    }
-    None
-}
-
-fn get_string_array_value<'a>(
-    current: &'a Table,
-    base: &'a Table,
-    key: &str,
-    required: bool,
-) -> Option<Vec<String>> {
-    if current.contains_key(key) {
-        return Some(
-            current[key]
-                .as_array()
-                .unwrap()
+impl Configuration {
+    /// Finds environment by name. Without a name the only configured environment is used.
+    pub fn environment(&self, name: Option<&str>) -> Result<Option<&Scope>, String> {
+        let known = || {
+            self.environments
                 .iter()
-                .map(|x| x.as_str().unwrap().to_string())
-                .collect(),
-        );
-    } else if base.contains_key(key) {
-        return Some(
-            base[key]
-                .as_array()
-                .unwrap()
+                .map(|scope| format!("`{}`", scope.name))
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        match name {
+            Some(name) => self
+                .environments
                 .iter()
-                .map(|x| x.as_str().unwrap().to_string())
-                .collect(),
-        );
-    } else if required {
-        panic!("Can't find config value for `{}` key.", key);
+                .find(|scope| scope.name == name)
+                .map(Some)
+                .ok_or_else(|| {
+                    format!(
+                        "Environment `{}` is not found in config. Known environments: {}.",
+                        name,
+                        known()
+                    )
+                }),
+            None if self.environments.len() > 1 => Err(format!(
+                "Multiple environments found in config ({}). Choose one with `--env` or `OPDAY_ENV`.",
+                known()
+            )),
+            None => Ok(self.environments.first()),
+        }
+    }
+
+    /// Same as `environment`, but config without environments is an error as well.
+    pub fn required_environment(&self, name: Option<&str>) -> Result<&Scope, OpdayError> {
+        self.environment(name)
+            .map_err(OpdayError::Config)?
+            .ok_or_else(|| OpdayError::Config("No environments found in config.".to_owned()))
+    }
+
+    /// Uses the key from `-i` for every environment instead of configured ones.
+    pub fn override_ssh_private_key(&mut self, private_key: &str) {
+        for scope in self.environments.iter_mut() {
+            scope.ssh_private_key = Some(private_key.to_owned());
+            for host in scope.hosts.iter_mut() {
+                host.ssh_private_key = None;
+            }
+        }
     }
-    None
-}
 
-fn make_parsing_scope(current: &Table, base: &Table) -> Scope {
-    let registry = get_string_value(current, base, "registry", true);
-    let hosts = get_string_array_value(current, base, "hosts", true);
-    let registry_auth_config = get_string_value(current, base, "registry_auth_config", true);
-    let registry_export_auth_config =
-        get_string_value(current, base, "registry_export_auth_config", true);
-    let docker_compose_overrides =
-        get_string_array_value(current, base, "docker_compose_overrides", true);
-    let ssh_private_key = get_string_value(current, base, "ssh_private_key", false);
-    let export_path = get_string_value(current, base, "export_path", true);
+    /// Makes local paths in config relative to the config file directory instead of working directory.
+    ///
+    /// Docker compose overrides stay relative to `path`, because the same relative
+    /// paths are used under `export_path` on remote hosts.
+    fn resolve_paths(&mut self, base: &Path) {
+        if base.as_os_str().is_empty() {
+            return;
+        }
+        self.path = if self.path == "." {
+            base.to_string_lossy().into_owned()
+        } else {
+            base.join(&self.path).to_string_lossy().into_owned()
+        };
 
-    Scope {
-        hosts: hosts.unwrap(),
-        registry: registry.unwrap(),
-        registry_auth_config: registry_auth_config.unwrap(),
-        registry_export_auth_config: registry_export_auth_config.unwrap(),
-        docker_compose_overrides: docker_compose_overrides.unwrap(),
-        ssh_private_key,
-        export_path: export_path.unwrap(),
+        let resolve = |value: &str| -> String {
+            if value.starts_with('~') {
+                return value.to_owned();
+            }
+            base.join(value).to_string_lossy().into_owned()
+        };
+        for scope in self.environments.iter_mut() {
+            scope.ssh_private_key = scope.ssh_private_key.as_deref().map(resolve);
+            scope.registry_auth_config = resolve(&scope.registry_auth_config);
+            for host in scope.hosts.iter_mut() {
+                host.ssh_private_key = host.ssh_private_key.as_deref().map(resolve);
+            }
+        }
     }
 }
 
-pub fn read_configuration_raw(content: &str) -> Result<Configuration, Box<dyn std::error::Error>> {
-    let cfg_parse: Result<Table, toml::de::Error> = content.parse();
+/// Remote host with its own settings, which override ones of environment.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Host {
+    /// Host name or IP address, optionally with `user@`
+    pub address: String,
+    /// Ssh user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Ssh port
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Identity file (private key) for ssh
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_private_key: Option<String>,
+    /// Roles of host, e.g. `web`, `worker` or `db`
+    #[serde(default, alias = "labels", skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    /// Directory on host to copy project files to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub export_path: Option<String>,
+    /// Docker compose override files relative to `path` used on this host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub docker_compose_overrides: Option<Vec<String>>,
+}
 
-    if cfg_parse.is_err() {
-        panic!("Config parsing error: {:?}", cfg_parse.err().unwrap())
+impl Host {
+    pub fn new(address: &str) -> Host {
+        Host {
+            address: address.to_owned(),
+            ..Default::default()
+        }
     }
+}
+
+/// Host written as plain address in config equals the address.
+impl PartialEq<&str> for Host {
+    fn eq(&self, other: &&str) -> bool {
+        *self == Host::new(other)
+    }
+}
 
-    let cfg: Table = cfg_parse.unwrap();
+/// Host as written in config: `user@address` string or table with host settings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum HostFile {
+    Address(String),
+    Table(Host),
+}
+
+// Not derived: untagged enum hides errors of host table behind "did not match any variant".
+impl<'de> Deserialize<'de> for HostFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct HostFileVisitor;
 
-    let mut environments: Vec<Scope> = vec![];
-    let mut base_scope = Table::new();
+        impl<'de> Visitor<'de> for HostFileVisitor {
+            type Value = HostFile;
 
-    if cfg.contains_key("environments") {
-        let val: Table = cfg["environments"].as_table().unwrap().clone();
-        for (key, value) in val.iter() {
-            if value.is_table() {
-                continue;
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("host address or table with `address`")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<HostFile, E> {
+                Ok(HostFile::Address(value.to_owned()))
             }
-            debug!("Looking into key: {:?}; Value: {:?}", key, value);
-            base_scope.insert(key.clone(), value.clone());
-        }
 
-        for (key, value) in val.iter() {
-            if !value.is_table() {
-                continue;
+            fn visit_map<M: MapAccess<'de>>(self, map: M) -> Result<HostFile, M::Error> {
+                Host::deserialize(de::value::MapAccessDeserializer::new(map)).map(HostFile::Table)
             }
-            debug!("Filling into environment: {:?}", key);
+        }
+
+        deserializer.deserialize_any(HostFileVisitor)
+    }
+}
+
+impl From<HostFile> for Host {
+    fn from(host: HostFile) -> Host {
+        match host {
+            HostFile::Address(address) => Host::new(&address),
+            HostFile::Table(host) => host,
+        }
+    }
+}
+
+/// How ssh treats host keys missing in or differing from `known_hosts`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum KnownHosts {
+    /// Connect only to hosts with known keys
+    Strict,
+    /// Remember keys of new hosts, refuse changed keys
+    AcceptNew,
+    /// Don't check host keys
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HookStage {
+    PreBuild,
+    PostBuild,
+    PreDeploy,
+    PostDeploy,
+    OnFailure,
+}
+
+/// Command run at pipeline stage. Failed hook aborts the pipeline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Hook {
+    /// Stage to run at
+    pub stage: HookStage,
+    /// Shell command, `OPDAY_ENV`, `OPDAY_HOSTS`, `OPDAY_HOST` and build args are available as variables
+    pub command: String,
+    /// Run on hosts in `export_path` instead of locally
+    #[serde(default)]
+    pub remote: bool,
+    /// Run on hosts in a new container of this service with `docker compose run --rm`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// Run remote hook only on the first host, e.g. for migrations
+    #[serde(default)]
+    pub once: bool,
+}
+
+/// Environment settings as written in config file.
+///
+/// Every value is optional, because it can be inherited from the `[environments]` table.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ScopeFile {
+    /// Remote hosts to deploy to: `user@host` strings or tables with `address` and host settings
+    pub hosts: Option<Vec<HostFile>>,
+    /// Directory on remote hosts to copy project files to
+    pub export_path: Option<String>,
+    /// Container registry host, e.g. `registry.digitalocean.com`
+    pub registry: Option<String>,
+    /// Local docker config with registry credentials
+    pub registry_auth_config: Option<String>,
+    /// Path on remote hosts to export registry credentials to
+    pub registry_export_auth_config: Option<String>,
+    /// Docker compose override files relative to `path`
+    pub docker_compose_overrides: Option<Vec<String>>,
+    /// Identity file (private key) for ssh
+    pub ssh_private_key: Option<String>,
+    /// Ssh user for hosts without `user@`
+    pub ssh_user: Option<String>,
+    /// Ssh port
+    pub ssh_port: Option<u16>,
+    /// Jump host to connect through (ssh `ProxyJump`), e.g. `user@bastion:22`
+    pub ssh_jump_host: Option<String>,
+    /// Host key checking: `strict`, `accept_new` or `off` (ssh config by default)
+    pub ssh_known_hosts: Option<KnownHosts>,
+    /// Seconds to wait for ssh connection
+    pub ssh_connect_timeout: Option<u64>,
+    /// Seconds between keepalive messages on idle connection
+    pub ssh_keepalive_interval: Option<u64>,
+    /// Reuse one ssh connection per host for all commands (default `true`)
+    pub ssh_multiplex: Option<bool>,
+    /// Seconds after which remote commands of deploy are killed (no limit by default)
+    pub command_timeout: Option<u64>,
+    /// Attempts to repeat pull, sync and login after network or registry failures (default `2`)
+    pub retries: Option<u32>,
+    /// Seconds before the first retry, doubled for every next one (default `2`)
+    pub retry_delay: Option<u64>,
+    /// Build args applied before ones from command line
+    pub build_arg: Option<BTreeMap<String, String>>,
+    /// Extra files and directories relative to `path` to upload on deploy besides compose files
+    pub sync_files: Option<Vec<String>>,
+    /// Commands to run at build and deploy stages
+    pub hooks: Option<Vec<Hook>>,
+    /// Compose services per host role, e.g. `db = ["postgres"]`; services not listed run on every host
+    pub placement: Option<BTreeMap<String, Vec<String>>>,
+}
+
+const SCOPE_FIELDS: &[&str] = &[
+    "hosts",
+    "export_path",
+    "registry",
+    "registry_auth_config",
+    "registry_export_auth_config",
+    "docker_compose_overrides",
+    "ssh_private_key",
+    "ssh_user",
+    "ssh_port",
+    "ssh_jump_host",
+    "ssh_known_hosts",
+    "ssh_connect_timeout",
+    "ssh_keepalive_interval",
+    "ssh_multiplex",
+    "command_timeout",
+    "retries",
+    "retry_delay",
+    "build_arg",
+    "sync_files",
+    "hooks",
+    "placement",
+];
+
+impl ScopeFile {
+    fn merge(self, base: &ScopeFile) -> ScopeFile {
+        ScopeFile {
+            hosts: self.hosts.or_else(|| base.hosts.clone()),
+            export_path: self.export_path.or_else(|| base.export_path.clone()),
+            registry: self.registry.or_else(|| base.registry.clone()),
+            registry_auth_config: self
+                .registry_auth_config
+                .or_else(|| base.registry_auth_config.clone()),
+            registry_export_auth_config: self
+                .registry_export_auth_config
+                .or_else(|| base.registry_export_auth_config.clone()),
+            docker_compose_overrides: self
+                .docker_compose_overrides
+                .or_else(|| base.docker_compose_overrides.clone()),
+            ssh_private_key: self
+                .ssh_private_key
+                .or_else(|| base.ssh_private_key.clone()),
+            ssh_user: self.ssh_user.or_else(|| base.ssh_user.clone()),
+            ssh_port: self.ssh_port.or(base.ssh_port),
+            ssh_jump_host: self.ssh_jump_host.or_else(|| base.ssh_jump_host.clone()),
+            ssh_known_hosts: self.ssh_known_hosts.or(base.ssh_known_hosts),
+            ssh_connect_timeout: self.ssh_connect_timeout.or(base.ssh_connect_timeout),
+            ssh_keepalive_interval: self.ssh_keepalive_interval.or(base.ssh_keepalive_interval),
+            ssh_multiplex: self.ssh_multiplex.or(base.ssh_multiplex),
+            command_timeout: self.command_timeout.or(base.command_timeout),
+            retries: self.retries.or(base.retries),
+            retry_delay: self.retry_delay.or(base.retry_delay),
+            build_arg: self.build_arg.or_else(|| base.build_arg.clone()),
+            sync_files: self.sync_files.or_else(|| base.sync_files.clone()),
+            hooks: self.hooks.or_else(|| base.hooks.clone()),
+            placement: self.placement.or_else(|| base.placement.clone()),
+        }
+    }
 
-            let scope = make_parsing_scope(value.as_table().unwrap(), &base_scope);
-            environments.push(scope);
+    fn into_scope(self, name: &str) -> Result<Scope, String> {
+        fn required<T>(value: Option<T>, name: &str, key: &str) -> Result<T, String> {
+            value.ok_or_else(|| {
+                format!(
+                    "Can't find config value for `{}` key in `{}` environment.",
+                    key, name
+                )
+            })
         }
+
+        Ok(Scope {
+            name: name.to_owned(),
+            hosts: required(self.hosts, name, "hosts")?
+                .into_iter()
+                .map(Host::from)
+                .collect(),
+            export_path: required(self.export_path, name, "export_path")?,
+            registry: required(self.registry, name, "registry")?,
+            registry_auth_config: required(
+                self.registry_auth_config,
+                name,
+                "registry_auth_config",
+            )?,
+            registry_export_auth_config: required(
+                self.registry_export_auth_config,
+                name,
+                "registry_export_auth_config",
+            )?,
+            docker_compose_overrides: required(
+                self.docker_compose_overrides,
+                name,
+                "docker_compose_overrides",
+            )?,
+            ssh_private_key: self.ssh_private_key,
+            ssh_user: self.ssh_user,
+            ssh_port: self.ssh_port,
+            ssh_jump_host: self.ssh_jump_host,
+            ssh_known_hosts: self.ssh_known_hosts,
+            ssh_connect_timeout: self.ssh_connect_timeout,
+            ssh_keepalive_interval: self.ssh_keepalive_interval,
+            ssh_multiplex: self.ssh_multiplex.unwrap_or(true),
+            command_timeout: self.command_timeout,
+            retries: self.retries.unwrap_or(2),
+            retry_delay: self.retry_delay.unwrap_or(2),
+            build_arg: self.build_arg.unwrap_or_default(),
+            sync_files: self.sync_files.unwrap_or_default(),
+            hooks: self.hooks.unwrap_or_default(),
+            placement: self.placement.unwrap_or_default(),
+        })
+    }
+}
+
+/// The `[environments]` table: shared values on the top level and one sub-table per environment.
+/// Environments keep the order of config file, the first one is the default.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct EnvironmentsFile {
+    #[serde(flatten)]
+    pub base: ScopeFile,
+    #[serde(flatten)]
+    pub named: IndexMap<String, ScopeFile>,
+}
+
+impl<'de> Deserialize<'de> for EnvironmentsFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let table = Table::deserialize(deserializer)?;
+
+        let mut base = Table::new();
+        let mut named = IndexMap::new();
+        for (key, value) in table.into_iter() {
+            match value {
+                // tables of known fields like `build_arg` are shared values, not environments
+                toml::Value::Table(env_table) if !SCOPE_FIELDS.contains(&key.as_str()) => {
+                    debug!("Filling into environment: {:?}", key);
+                    let scope = toml::Value::Table(env_table)
+                        .try_into::<ScopeFile>()
+                        .map_err(|e| {
+                            serde::de::Error::custom(format!(
+                                "in `environments.{}`: {}",
+                                key,
+                                with_suggestion(e.message(), SCOPE_FIELDS)
+                            ))
+                        })?;
+                    named.insert(key, scope);
+                }
+                value => {
+                    debug!("Looking into key: {:?}; Value: {:?}", key, value);
+                    base.insert(key, value);
+                }
+            }
+        }
+
+        let base = toml::Value::Table(base)
+            .try_into::<ScopeFile>()
+            .map_err(|e| {
+                serde::de::Error::custom(format!(
+                    "in `environments`: {}",
+                    with_suggestion(e.message(), SCOPE_FIELDS)
+                ))
+            })?;
+        Ok(EnvironmentsFile { base, named })
+    }
+}
+
+impl JsonSchema for EnvironmentsFile {
+    fn schema_name() -> String {
+        "EnvironmentsFile".to_owned()
     }
 
-    let mut path = ".".to_string();
-    if cfg.contains_key("path") {
-        path = cfg["path"].as_str().unwrap().to_string();
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = ScopeFile::json_schema(gen).into_object();
+        schema.metadata().description =
+            Some("Values shared by all environments and one table per environment name".to_owned());
+        schema.object().additional_properties = Some(Box::new(gen.subschema_for::<ScopeFile>()));
+        Schema::Object(schema)
     }
+}
+
+/// Config file as written by user.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigurationFile {
+    /// Project directory with docker compose files (default `.`)
+    pub path: Option<String>,
+    /// Main docker compose file relative to `path` (default `docker-compose.yaml`)
+    pub docker_compose_file: Option<String>,
+    /// Deployment environments
+    pub environments: Option<EnvironmentsFile>,
+    /// Own settings of providers, one table per provider name, e.g. `[providers.docker]`
+    #[schemars(with = "Option<BTreeMap<String, serde_json::Value>>")]
+    pub providers: Option<BTreeMap<String, toml::Value>>,
+}
 
-    let mut docker_compose_file = "docker-compose.yaml".to_string();
-    if cfg.contains_key("docker_compose_file") {
-        docker_compose_file = cfg["docker_compose_file"].as_str().unwrap().to_string();
+const CONFIGURATION_FIELDS: &[&str] = &["path", "docker_compose_file", "environments", "providers"];
+
+impl ConfigurationFile {
+    pub fn into_configuration(self) -> Result<Configuration, String> {
+        let environments_file = self.environments.unwrap_or_default();
+        let mut environments = vec![];
+        for (name, scope) in environments_file.named.into_iter() {
+            environments.push(scope.merge(&environments_file.base).into_scope(&name)?);
+        }
+
+        Ok(Configuration {
+            path: self.path.unwrap_or_else(|| ".".to_string()),
+            docker_compose_file: self
+                .docker_compose_file
+                .unwrap_or_else(|| "docker-compose.yaml".to_string()),
+            environments,
+            providers: self.providers.unwrap_or_default(),
+        })
     }
-    let config: Configuration = Configuration {
-        path,
-        docker_compose_file,
-        environments,
+}
+
+/// Appends a hint with the closest known key to serde's "unknown field" message.
+fn with_suggestion(message: &str, fields: &[&str]) -> String {
+    let unknown = message
+        .strip_prefix("unknown field `")
+        .and_then(|rest| rest.split('`').next());
+    let Some(unknown) = unknown else {
+        return message.to_owned();
     };
-    Ok(config)
+
+    let closest = fields
+        .iter()
+        .map(|field| (strsim::jaro_winkler(unknown, field), field))
+        .filter(|(score, _)| *score > 0.8)
+        .max_by(|a, b| a.0.total_cmp(&b.0));
+    match closest {
+        Some((_, field)) => format!("{}; did you mean `{}`?", message, field),
+        None => message.to_owned(),
+    }
+}
+
+/// JSON Schema of config file for editor completion and validation.
+pub fn configuration_schema() -> String {
+    let schema = schema_for!(ConfigurationFile);
+    serde_json::to_string_pretty(&schema).expect("Could not serialize schema.")
+}
+
+/// Supported formats of configuration file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Detects format by file extension. Unknown extensions are read as TOML.
+    pub fn from_path(path: &Path) -> ConfigFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            Some("json") => ConfigFormat::Json,
+            _ => ConfigFormat::Toml,
+        }
+    }
+}
+
+/// Config file names looked up when no config is passed explicitly, in priority order.
+pub const DEFAULT_CONFIG_FILES: [&str; 4] = ["opday.toml", "opday.yaml", "opday.yml", "opday.json"];
+
+/// Returns the first default config file existing in `dir`.
+pub fn find_default_configuration(dir: &Path) -> Option<PathBuf> {
+    DEFAULT_CONFIG_FILES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Looks for default config file in `start` and its parent directories like git does.
+pub fn discover_configuration(start: &Path) -> Option<PathBuf> {
+    start.ancestors().find_map(find_default_configuration)
+}
+
+fn parse_table(content: &str, format: ConfigFormat) -> Result<Table, String> {
+    // Empty YAML and JSON documents are null, not empty tables
+    if format != ConfigFormat::Toml && content.trim().is_empty() {
+        return Ok(Table::new());
+    }
+    match format {
+        ConfigFormat::Toml => content.parse::<Table>().map_err(|e| e.to_string()),
+        ConfigFormat::Yaml => serde_yaml::from_str::<Table>(content).map_err(|e| e.to_string()),
+        ConfigFormat::Json => serde_json::from_str::<Table>(content).map_err(|e| e.to_string()),
+    }
+}
+
+pub fn read_configuration_raw(content: &str) -> Result<Configuration, Box<dyn std::error::Error>> {
+    read_configuration_raw_with_format(content, ConfigFormat::Toml)
+}
+
+pub fn read_configuration_raw_with_format(
+    content: &str,
+    format: ConfigFormat,
+) -> Result<Configuration, Box<dyn std::error::Error>> {
+    let cfg: Table = parse_table(content, format)
+        .map_err(|e| OpdayError::Config(format!("Config parsing error: {}", e)))?;
+
+    let file = toml::Value::Table(cfg)
+        .try_into::<ConfigurationFile>()
+        .map_err(|e| OpdayError::Config(with_suggestion(e.message(), CONFIGURATION_FIELDS)))?;
+    Ok(file.into_configuration().map_err(OpdayError::Config)?)
 }
 
 pub fn read_configuration(path: &Path) -> Result<Configuration, Box<dyn std::error::Error>> {
     let path = std::path::Path::new(&path);
-    let file = match std::fs::read_to_string(path) {
-        Ok(f) => f,
-        Err(e) => {
-            panic!("No config file found in {} ({}).", path.display(), e)
-        }
-    };
-    read_configuration_raw(&file)
+    let file = std::fs::read_to_string(path).map_err(|e| {
+        OpdayError::Config(format!(
+            "No config file found in {} ({}).",
+            path.display(),
+            e
+        ))
+    })?;
+    let mut config = read_configuration_raw_with_format(&file, ConfigFormat::from_path(path))?;
+    config.resolve_paths(path.parent().unwrap_or(Path::new("")));
+    Ok(config)
 }
 
 #[cfg(test)]
@@ -230,4 +781,450 @@ mod tests {
             "aexport_path".to_string()
         );
     }
+
+    #[test]
+    fn test_ssh_settings() {
+        let config = read_configuration_raw(
+            r#"
+            [environments]
+            registry = "registry"
+            registry_auth_config = "auth"
+            registry_export_auth_config = "export_auth"
+            docker_compose_overrides = []
+            export_path = "/root/app"
+            ssh_user = "deploy"
+            ssh_known_hosts = "accept_new"
+
+            [environments.prod]
+            hosts = ["one", "root@two"]
+            ssh_port = 2222
+            ssh_jump_host = "me@bastion"
+            ssh_multiplex = false
+            "#,
+        )
+        .unwrap();
+        let scope = &config.environments[0];
+        assert_eq!(scope.ssh_user.as_deref(), Some("deploy"));
+        assert_eq!(scope.ssh_port, Some(2222));
+        assert_eq!(scope.ssh_jump_host.as_deref(), Some("me@bastion"));
+        assert_eq!(scope.ssh_known_hosts, Some(KnownHosts::AcceptNew));
+        assert_eq!(scope.ssh_connect_timeout, None);
+        assert!(!scope.ssh_multiplex);
+    }
+
+    #[test]
+    fn test_structured_hosts() {
+        let config = read_configuration_raw(
+            r#"
+            [environments.prod]
+            export_path = "/root/app"
+            registry = "registry"
+            registry_auth_config = "auth"
+            registry_export_auth_config = "export_auth"
+            docker_compose_overrides = ["docker-compose.prod.yaml"]
+            ssh_user = "root"
+            hosts = [
+                "10.0.0.5",
+                { address = "10.0.0.6", user = "deploy", port = 2222, roles = ["worker"], export_path = "/srv/app" },
+            ]
+            "#,
+        )
+        .unwrap();
+        let scope = &config.environments[0];
+        assert_eq!(scope.addresses(), vec!["10.0.0.5", "10.0.0.6"]);
+        assert_eq!(scope.hosts[0], "10.0.0.5");
+        assert_ne!(scope.hosts[1], "10.0.0.6");
+
+        let web = scope.for_host(&scope.hosts[0]);
+        assert_eq!(web.ssh_user.as_deref(), Some("root"));
+        assert_eq!(web.export_path, "/root/app");
+
+        let worker = scope.for_host(&scope.hosts[1]);
+        assert_eq!(worker.hosts[0].roles, vec!["worker"]);
+        assert_eq!(worker.ssh_user.as_deref(), Some("deploy"));
+        assert_eq!(worker.ssh_port, Some(2222));
+        assert_eq!(worker.export_path, "/srv/app");
+        assert_eq!(
+            worker.docker_compose_overrides,
+            vec!["docker-compose.prod.yaml"]
+        );
+
+        let error = read_configuration_raw(
+            r#"
+            [environments.prod]
+            hosts = [{ adress = "10.0.0.6" }]
+            "#,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("unknown field `adress`"), "{}", error);
+    }
+
+    #[test]
+    fn test_placement() {
+        let config = read_configuration_raw(
+            r#"
+            [environments]
+            export_path = "/root/app"
+            registry = "registry"
+            registry_auth_config = "auth"
+            registry_export_auth_config = "export_auth"
+            docker_compose_overrides = []
+
+            [environments.placement]
+            web = ["backend", "nginx"]
+            db = ["postgres"]
+
+            [environments.prod]
+            hosts = [
+                { address = "10.0.0.5", roles = ["web"] },
+                { address = "10.0.0.6", roles = ["web", "db"] },
+                "10.0.0.7",
+            ]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.environments.len(), 1);
+        let scope = &config.environments[0];
+        let services = ["backend", "nginx", "postgres", "worker"];
+        assert_eq!(
+            scope.placed_services(&scope.hosts[0], &services),
+            vec!["backend", "nginx", "worker"]
+        );
+        assert_eq!(scope.placed_services(&scope.hosts[1], &services), services);
+        assert_eq!(
+            scope.placed_services(&scope.hosts[2], &services),
+            vec!["worker"]
+        );
+        assert!(scope.check_placement(&services).is_ok());
+
+        let error = scope.check_placement(&["backend", "nginx"]).unwrap_err();
+        assert!(
+            error.contains("Service `postgres` placed on role `db`"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_override_ssh_private_key() {
+        let mut config = read_configuration_raw(
+            r#"
+            [environments]
+            ssh_private_key = "akey"
+            registry = "registry"
+            registry_auth_config = "auth"
+            registry_export_auth_config = "export_auth"
+            docker_compose_overrides = []
+            export_path = "/root/app"
+
+            [environments.prod]
+            hosts = ["root@one"]
+
+            [environments.stage]
+            hosts = ["root@two"]
+            ssh_private_key = "bkey"
+            "#,
+        )
+        .unwrap();
+        config.override_ssh_private_key("~/.ssh/deploy");
+        for scope in &config.environments {
+            assert_eq!(scope.ssh_private_key.as_deref(), Some("~/.ssh/deploy"));
+        }
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("opday.toml")),
+            ConfigFormat::Toml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("opday.yaml")),
+            ConfigFormat::Yaml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("opday.yml")),
+            ConfigFormat::Yaml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("opday.json")),
+            ConfigFormat::Json
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("myconfig")),
+            ConfigFormat::Toml
+        );
+    }
+
+    #[test]
+    fn test_empty_yaml_and_json() {
+        assert!(read_configuration_raw_with_format("", ConfigFormat::Yaml).is_ok());
+        assert!(read_configuration_raw_with_format("", ConfigFormat::Json).is_ok());
+    }
+
+    #[test]
+    fn test_yaml_environment_order_load() {
+        let yaml_data = r#"
+path: path
+docker_compose_file: file
+environments:
+  registry: aregistry
+  registry_auth_config: aauth
+  registry_export_auth_config: aexport_auth
+  docker_compose_overrides: [aoverride]
+  export_path: aexport_path
+  b:
+    ssh_private_key: bkey
+    hosts: [bhost]
+"#;
+
+        let config = read_configuration_raw_with_format(yaml_data, ConfigFormat::Yaml).unwrap();
+        assert_eq!(config.path, "path");
+        assert_eq!(config.docker_compose_file, "file");
+        assert_eq!(config.environments.len(), 1);
+        assert_eq!(
+            config.environments[0].ssh_private_key,
+            Some("bkey".to_string())
+        );
+        assert_eq!(config.environments[0].registry, "aregistry");
+        assert_eq!(config.environments[0].addresses(), vec!["bhost"]);
+    }
+
+    #[test]
+    fn test_json_environment_order_load() {
+        let json_data = r#"{
+            "path": "path",
+            "environments": {
+                "registry": "aregistry",
+                "registry_auth_config": "aauth",
+                "registry_export_auth_config": "aexport_auth",
+                "docker_compose_overrides": ["aoverride"],
+                "export_path": "aexport_path",
+                "b": {"hosts": ["bhost"]}
+            }
+        }"#;
+
+        let config = read_configuration_raw_with_format(json_data, ConfigFormat::Json).unwrap();
+        assert_eq!(config.path, "path");
+        assert_eq!(config.docker_compose_file, "docker-compose.yaml");
+        assert_eq!(config.environments.len(), 1);
+        assert_eq!(config.environments[0].ssh_private_key, None);
+        assert_eq!(config.environments[0].addresses(), vec!["bhost"]);
+        assert_eq!(config.environments[0].export_path, "aexport_path");
+    }
+
+    #[test]
+    fn test_find_default_configuration() {
+        let dir = Path::new("tests/01_trivial-backend-no-storage");
+        assert_eq!(
+            find_default_configuration(dir),
+            Some(dir.join("opday.toml"))
+        );
+        assert_eq!(find_default_configuration(Path::new("src")), None);
+    }
+
+    #[test]
+    fn test_unknown_key_suggestion() {
+        let toml_data = r#"
+        [environments]
+        docker_compose_override = ["aoverride"]
+        "#;
+
+        let error = read_configuration_raw(toml_data).err().unwrap();
+        assert!(error
+            .to_string()
+            .contains("did you mean `docker_compose_overrides`?"));
+    }
+
+    #[test]
+    fn test_unknown_key_in_environment_suggestion() {
+        let toml_data = r#"
+        [environments.prod]
+        host = ["ahost"]
+        "#;
+
+        let error = read_configuration_raw(toml_data).err().unwrap().to_string();
+        assert!(error.contains("environments.prod"), "{}", error);
+        assert!(error.contains("did you mean `hosts`?"), "{}", error);
+    }
+
+    #[test]
+    fn test_unknown_top_level_key() {
+        let toml_data = r#"
+        docker_compose = "file"
+        "#;
+
+        let error = read_configuration_raw(toml_data).err().unwrap().to_string();
+        assert!(
+            error.contains("did you mean `docker_compose_file`?"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_missing_required_key() {
+        let toml_data = r#"
+        [environments.prod]
+        hosts = ["ahost"]
+        "#;
+
+        let error = read_configuration_raw(toml_data).err().unwrap().to_string();
+        assert_eq!(
+            error,
+            "Can't find config value for `export_path` key in `prod` environment."
+        );
+    }
+
+    #[test]
+    fn test_environment_build_arg() {
+        let config = read_configuration(Path::new(
+            "tests/02_simple-backend-with-database/opday.toml",
+        ))
+        .unwrap();
+        let scope = &config.environments[0];
+        assert_eq!(scope.name, "prod");
+        assert_eq!(
+            scope.build_args_with(&["BACKEND_TAG=0.0.1".to_string()]),
+            vec!["HOST=http://example.com", "BACKEND_TAG=0.0.1"]
+        );
+    }
+
+    #[test]
+    fn test_configuration_schema() {
+        let schema: serde_json::Value = serde_json::from_str(&configuration_schema()).unwrap();
+        assert_eq!(schema["additionalProperties"], false);
+        assert!(schema["definitions"]["ScopeFile"]["properties"]["hosts"].is_object());
+        assert_eq!(
+            schema["definitions"]["EnvironmentsFile"]["additionalProperties"]["$ref"],
+            "#/definitions/ScopeFile"
+        );
+    }
+
+    #[test]
+    fn test_discover_configuration() {
+        let dir = Path::new("tests/01_trivial-backend-no-storage");
+        assert_eq!(
+            discover_configuration(&dir.join("test-backend")),
+            Some(dir.join("opday.toml"))
+        );
+    }
+
+    #[test]
+    fn test_paths_relative_to_config_file() {
+        let config =
+            read_configuration(Path::new("tests/01_trivial-backend-no-storage/opday.toml"))
+                .unwrap();
+        assert_eq!(config.path, "tests/01_trivial-backend-no-storage");
+        let scope = &config.environments[0];
+        assert_eq!(
+            scope.ssh_private_key,
+            Some("~/.ssh/dkrpublish_rsa".to_string())
+        );
+        assert_eq!(
+            scope.registry_auth_config,
+            "tests/01_trivial-backend-no-storage/.secrets/docker-config.json"
+        );
+        assert_eq!(
+            scope.docker_compose_overrides,
+            vec!["docker-compose.override-run.prod.yaml"]
+        );
+    }
+
+    #[test]
+    fn test_select_environment() {
+        let toml_data = r#"
+        [environments]
+        registry = "registry"
+        registry_auth_config = "auth"
+        registry_export_auth_config = "export_auth"
+        docker_compose_overrides = []
+        export_path = "export_path"
+
+        [environments.prod]
+        hosts = ["prodhost"]
+
+        [environments.stage]
+        hosts = ["stagehost"]
+        "#;
+
+        let config = read_configuration_raw(toml_data).unwrap();
+        assert_eq!(
+            config
+                .environment(Some("stage"))
+                .unwrap()
+                .unwrap()
+                .addresses(),
+            vec!["stagehost"]
+        );
+        assert!(config.environment(None).is_err());
+        assert_eq!(
+            config.environment(Some("dev")).err().unwrap(),
+            "Environment `dev` is not found in config. Known environments: `prod`, `stage`."
+        );
+        assert!(read_configuration_raw("")
+            .unwrap()
+            .environment(None)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_environments_file_order() {
+        let shared = r#"
+        [environments]
+        registry = "registry"
+        registry_auth_config = "auth"
+        registry_export_auth_config = "export_auth"
+        docker_compose_overrides = []
+        export_path = "export_path"
+        "#;
+        let names = |config: &Configuration| -> Vec<String> {
+            config.environments.iter().map(|e| e.name.clone()).collect()
+        };
+
+        let toml_data = shared.to_owned()
+            + r#"
+        [environments.stage]
+        hosts = ["stagehost"]
+
+        [environments.prod]
+        hosts = ["prodhost"]
+
+        [environments.dev]
+        hosts = ["devhost"]
+        "#;
+        let config = read_configuration_raw(&toml_data).unwrap();
+        assert_eq!(names(&config), ["stage", "prod", "dev"]);
+        assert_eq!(
+            config.environment(Some("qa")).err().unwrap(),
+            "Environment `qa` is not found in config. Known environments: `stage`, `prod`, `dev`."
+        );
+
+        let yaml_data = r#"
+environments:
+  registry: registry
+  registry_auth_config: auth
+  registry_export_auth_config: export_auth
+  docker_compose_overrides: []
+  export_path: export_path
+  stage: {hosts: [stagehost]}
+  prod: {hosts: [prodhost]}
+"#;
+        let config = read_configuration_raw_with_format(yaml_data, ConfigFormat::Yaml).unwrap();
+        assert_eq!(names(&config), ["stage", "prod"]);
+
+        let json_data = r#"{"environments": {
+            "registry": "registry",
+            "registry_auth_config": "auth",
+            "registry_export_auth_config": "export_auth",
+            "docker_compose_overrides": [],
+            "export_path": "export_path",
+            "stage": {"hosts": ["stagehost"]},
+            "prod": {"hosts": ["prodhost"]}
+        }}"#;
+        let config = read_configuration_raw_with_format(json_data, ConfigFormat::Json).unwrap();
+        assert_eq!(names(&config), ["stage", "prod"]);
+    }
 }
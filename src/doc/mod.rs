@@ -1,8 +1,10 @@
 /// # User documentation
 ///
 /// Here will be more examples of how to use the tool.
-
 pub mod user {
     #[doc = include_str!("getting_started.md")]
     pub mod getting_started {}
+
+    #[doc = include_str!("output.md")]
+    pub mod output {}
 }
@@ -0,0 +1,435 @@
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use log::debug;
+use serde::Serialize;
+
+use crate::config::{Configuration, Scope};
+use crate::error::OpdayError;
+use crate::exec::{
+    execute_short_command, execute_short_command_output, shell_quote, RemoteHostCall,
+};
+use crate::output::{self, Event};
+
+extern crate term;
+
+/// Free space on remote host below which deploys are likely to fail on image pulls.
+const MIN_FREE_SPACE_KB: u64 = 1024 * 1024;
+/// Free space on remote host below which we warn.
+const LOW_FREE_SPACE_KB: u64 = 5 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Serialize)]
+struct Check {
+    target: String,
+    name: String,
+    status: CheckStatus,
+    details: String,
+}
+
+impl Check {
+    fn new(target: &str, name: &str, status: CheckStatus, details: &str) -> Check {
+        Check {
+            target: target.to_owned(),
+            name: name.to_owned(),
+            status,
+            details: details.to_owned(),
+        }
+    }
+}
+
+/// First non-empty line of command output.
+fn first_line(output: &str) -> String {
+    output
+        .lines()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty())
+        .unwrap_or("")
+        .to_owned()
+}
+
+/// Major version from `2.24.5` or `v2.24.5` forms.
+fn parse_major_version(version: &str) -> Option<u32> {
+    version
+        .trim()
+        .trim_start_matches('v')
+        .split('.')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Available kilobytes from `df -Pk` output.
+fn parse_df_available_kb(output: &str) -> Option<u64> {
+    output
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse()
+        .ok()
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+fn check_local_tools(checks: &mut Vec<Check>) {
+    let target = "local";
+
+    match execute_short_command("docker", vec!["compose", "version", "--short"], &[]) {
+        Ok(output) => {
+            let version = first_line(&output);
+            match parse_major_version(&version) {
+                Some(major) if major >= 2 => checks.push(Check::new(
+                    target,
+                    "docker compose",
+                    CheckStatus::Pass,
+                    &version,
+                )),
+                _ => checks.push(Check::new(
+                    target,
+                    "docker compose",
+                    CheckStatus::Fail,
+                    &format!("{} (v2 is required)", version),
+                )),
+            }
+        }
+        Err(_) => checks.push(Check::new(
+            target,
+            "docker compose",
+            CheckStatus::Fail,
+            "`docker compose` plugin is not available",
+        )),
+    }
+
+    let tools: [(&str, &str, Vec<&str>); 3] = [
+        ("docker buildx", "docker", vec!["buildx", "version"]),
+        ("rsync", "rsync", vec!["--version"]),
+        ("ssh", "ssh", vec!["-V"]),
+    ];
+    for (name, program, args) in tools {
+        match execute_short_command_output(program, args, &[]) {
+            // ssh prints its version to stderr
+            Ok((stdout, stderr)) => {
+                let version = first_line(&(stdout + &stderr));
+                checks.push(Check::new(target, name, CheckStatus::Pass, &version));
+            }
+            Err(_) => checks.push(Check::new(
+                target,
+                name,
+                CheckStatus::Fail,
+                &format!("`{}` is not available", name),
+            )),
+        }
+    }
+}
+
+fn check_scope_local(checks: &mut Vec<Check>, scope: &Scope) {
+    let target = format!("env {}", scope.name);
+
+    if let Some(private_key) = &scope.ssh_private_key {
+        let path = expand_home(private_key);
+        match std::fs::metadata(&path) {
+            Ok(metadata) => {
+                let mode = metadata.permissions().mode() & 0o777;
+                if mode & 0o077 == 0 {
+                    checks.push(Check::new(
+                        &target,
+                        "ssh key permissions",
+                        CheckStatus::Pass,
+                        &format!("{} {:o}", private_key, mode),
+                    ));
+                } else {
+                    checks.push(Check::new(
+                        &target,
+                        "ssh key permissions",
+                        CheckStatus::Fail,
+                        &format!(
+                            "{} is {:o}, run `chmod 600 {}`",
+                            private_key, mode, private_key
+                        ),
+                    ));
+                }
+            }
+            Err(e) => checks.push(Check::new(
+                &target,
+                "ssh key permissions",
+                CheckStatus::Fail,
+                &format!("{} ({})", private_key, e),
+            )),
+        }
+    }
+
+    if std::path::Path::new(&scope.registry_auth_config).is_file() {
+        checks.push(Check::new(
+            &target,
+            "local registry auth",
+            CheckStatus::Pass,
+            &scope.registry_auth_config,
+        ));
+    } else {
+        checks.push(Check::new(
+            &target,
+            "local registry auth",
+            CheckStatus::Warn,
+            &format!(
+                "{} not found, `docker login` needs username and password",
+                scope.registry_auth_config
+            ),
+        ));
+    }
+}
+
+fn ssh_command(
+    host: &RemoteHostCall,
+    address: &str,
+    command: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut params: Vec<String> = vec!["-o".to_owned(), "BatchMode=yes".to_owned()];
+    if host.connect_timeout.is_none() {
+        params.extend(["-o".to_owned(), "ConnectTimeout=10".to_owned()]);
+    }
+    params.extend(host.ssh_args(address, command, false));
+    let params2: Vec<&str> = params.iter().map(|s| s.as_str()).collect();
+    execute_short_command("ssh", params2, &[])
+}
+
+fn check_host(checks: &mut Vec<Check>, scope: &Scope, address: &str) {
+    let host = RemoteHostCall::new(scope);
+    let target = format!("{} {}", scope.name, address);
+
+    if let Err(e) = ssh_command(&host, address, "true") {
+        debug!("ssh connection failed: {}", e);
+        checks.push(Check::new(
+            &target,
+            "ssh connection",
+            CheckStatus::Fail,
+            "could not connect, remote checks are skipped",
+        ));
+        return;
+    }
+    checks.push(Check::new(&target, "ssh connection", CheckStatus::Pass, ""));
+
+    match ssh_command(
+        &host,
+        address,
+        "docker version --format '{{.Server.Version}}'",
+    ) {
+        Ok(output) => checks.push(Check::new(
+            &target,
+            "docker",
+            CheckStatus::Pass,
+            &first_line(&output),
+        )),
+        Err(_) => checks.push(Check::new(
+            &target,
+            "docker",
+            CheckStatus::Fail,
+            "docker is not installed or user has no access to docker daemon",
+        )),
+    }
+
+    match ssh_command(&host, address, "docker compose version --short") {
+        Ok(output) => {
+            let version = first_line(&output);
+            let status = match parse_major_version(&version) {
+                Some(major) if major >= 2 => CheckStatus::Pass,
+                _ => CheckStatus::Fail,
+            };
+            checks.push(Check::new(&target, "docker compose", status, &version));
+        }
+        Err(_) => checks.push(Check::new(
+            &target,
+            "docker compose",
+            CheckStatus::Fail,
+            "`docker compose` plugin is not available",
+        )),
+    }
+
+    let export_path = shell_quote(&scope.export_path);
+    let write_command = format!("mkdir -p {} && test -w {}", export_path, export_path);
+    match ssh_command(&host, address, &write_command) {
+        Ok(_) => checks.push(Check::new(
+            &target,
+            "export path",
+            CheckStatus::Pass,
+            &scope.export_path,
+        )),
+        Err(_) => checks.push(Check::new(
+            &target,
+            "export path",
+            CheckStatus::Fail,
+            &format!("{} is not writable", scope.export_path),
+        )),
+    }
+
+    let auth_command = format!(
+        "test -f {}",
+        shell_quote(&scope.registry_export_auth_config)
+    );
+    match ssh_command(&host, address, &auth_command) {
+        Ok(_) => checks.push(Check::new(
+            &target,
+            "registry auth",
+            CheckStatus::Pass,
+            &scope.registry_export_auth_config,
+        )),
+        Err(_) => checks.push(Check::new(
+            &target,
+            "registry auth",
+            CheckStatus::Fail,
+            &format!(
+                "{} not found, run `opday docker login`",
+                scope.registry_export_auth_config
+            ),
+        )),
+    }
+
+    let df_command = format!("df -Pk {}", export_path);
+    match ssh_command(&host, address, &df_command)
+        .ok()
+        .and_then(|output| parse_df_available_kb(&output))
+    {
+        Some(available) => {
+            let status = if available < MIN_FREE_SPACE_KB {
+                CheckStatus::Fail
+            } else if available < LOW_FREE_SPACE_KB {
+                CheckStatus::Warn
+            } else {
+                CheckStatus::Pass
+            };
+            checks.push(Check::new(
+                &target,
+                "disk space",
+                status,
+                &format!("{} MiB available", available / 1024),
+            ));
+        }
+        None => checks.push(Check::new(
+            &target,
+            "disk space",
+            CheckStatus::Warn,
+            "could not read free space",
+        )),
+    }
+}
+
+fn print_report(checks: &[Check]) {
+    if !output::is_text() {
+        output::emit(Event::Report {
+            kind: "doctor",
+            data: serde_json::to_value(checks).expect("Could not serialize checks."),
+        });
+        return;
+    }
+    let target_width = checks.iter().map(|c| c.target.len()).max().unwrap_or(0);
+    let name_width = checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+
+    let mut terminal = term::stdout();
+    for check in checks {
+        let (label, color) = match check.status {
+            CheckStatus::Pass => ("PASS", term::color::GREEN),
+            CheckStatus::Warn => ("WARN", term::color::YELLOW),
+            CheckStatus::Fail => ("FAIL", term::color::RED),
+        };
+        let line_start = format!(
+            "{:target_width$}  {:name_width$}  ",
+            check.target,
+            check.name,
+            target_width = target_width,
+            name_width = name_width,
+        );
+        match terminal.as_mut() {
+            Some(terminal) => {
+                let _ = write!(terminal, "{}", line_start);
+                let _ = terminal.fg(color);
+                let _ = write!(terminal, "{}", label);
+                let _ = terminal.reset();
+                let _ = writeln!(terminal, "  {}", check.details);
+            }
+            None => println!("{}{}  {}", line_start, label, check.details),
+        }
+    }
+}
+
+pub fn doctor(
+    config: &Configuration,
+    environment: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let scopes: Vec<&Scope> = match environment {
+        Some(_) => config
+            .environment(environment)
+            .map_err(OpdayError::Config)?
+            .into_iter()
+            .collect(),
+        None => config.environments.iter().collect(),
+    };
+
+    let mut checks: Vec<Check> = vec![];
+    check_local_tools(&mut checks);
+    for scope in scopes {
+        check_scope_local(&mut checks, scope);
+        for host in &scope.hosts {
+            check_host(&mut checks, &scope.for_host(host), &host.address);
+        }
+    }
+
+    print_report(&checks);
+
+    let failed: Vec<&Check> = checks
+        .iter()
+        .filter(|c| c.status == CheckStatus::Fail)
+        .collect();
+    if !failed.is_empty() {
+        let mut targets: Vec<String> = failed.iter().map(|c| c.target.clone()).collect();
+        targets.dedup();
+        return Err(OpdayError::Remote {
+            hosts: targets,
+            message: format!("{} of {} checks failed.", failed.len(), checks.len()),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_major_version() {
+        assert_eq!(parse_major_version("2.24.5"), Some(2));
+        assert_eq!(parse_major_version("v2.24.5-desktop.1\n"), Some(2));
+        assert_eq!(parse_major_version("1.29.2"), Some(1));
+        assert_eq!(parse_major_version(""), None);
+    }
+
+    #[test]
+    fn test_parse_df_available_kb() {
+        let output = "Filesystem     1024-blocks    Used Available Capacity Mounted on
+/dev/vda1         25215872 5523252  19676236      22% /
+";
+        assert_eq!(parse_df_available_kb(output), Some(19676236));
+        assert_eq!(parse_df_available_kb(""), None);
+    }
+
+    #[test]
+    fn test_first_line() {
+        assert_eq!(
+            first_line("\nOpenSSH_9.6p1, OpenSSL 3.0.13\n"),
+            "OpenSSH_9.6p1, OpenSSL 3.0.13"
+        );
+    }
+}
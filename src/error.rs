@@ -0,0 +1,188 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::exec::{ExecError, FailureKind};
+
+pub const EXIT_FAILURE: u8 = 1;
+pub const EXIT_INPUT: u8 = 2;
+pub const EXIT_CONFIG: u8 = 3;
+pub const EXIT_COMPOSE: u8 = 4;
+pub const EXIT_COMMAND: u8 = 5;
+pub const EXIT_REMOTE: u8 = 6;
+pub const EXIT_IO: u8 = 7;
+
+/// Error of opday command. Every kind has own exit code, so scripts can tell them apart.
+#[derive(Debug)]
+pub enum OpdayError {
+    /// Config file is missing or invalid, or environment is not found
+    Config(String),
+    /// Docker compose file can't be read or parsed
+    Compose { path: PathBuf, message: String },
+    /// Local or remote command failed
+    Command(ExecError),
+    /// Operation failed on remote hosts, e.g. lock is held or drift is found
+    Remote { hosts: Vec<String>, message: String },
+    /// Wrong arguments or answers
+    Input(String),
+    /// Local file can't be read or written
+    Io { path: PathBuf, source: io::Error },
+    /// Step of pipeline failed, the pipeline can be resumed from it
+    Step {
+        step: String,
+        source: Box<dyn Error>,
+    },
+}
+
+impl OpdayError {
+    pub fn remote(host: &str, message: &str) -> OpdayError {
+        OpdayError::Remote {
+            hosts: vec![host.to_owned()],
+            message: message.to_owned(),
+        }
+    }
+
+    pub fn io(path: &Path, source: io::Error) -> OpdayError {
+        OpdayError::Io {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            OpdayError::Config(_) => EXIT_CONFIG,
+            OpdayError::Compose { .. } => EXIT_COMPOSE,
+            OpdayError::Command(_) => EXIT_COMMAND,
+            OpdayError::Remote { .. } => EXIT_REMOTE,
+            OpdayError::Input(_) => EXIT_INPUT,
+            OpdayError::Io { .. } => EXIT_IO,
+            OpdayError::Step { source, .. } => exit_code(source.as_ref()),
+        }
+    }
+
+    /// What user can do about the error.
+    pub fn hint(&self) -> Option<String> {
+        match self {
+            OpdayError::Config(_) => Some(
+                "check the config file, `opday config schema` prints all known keys".to_owned(),
+            ),
+            OpdayError::Compose { .. } => {
+                Some("check the file with `docker compose config`".to_owned())
+            }
+            OpdayError::Command(error) => match (error.kind, error.program.as_str()) {
+                (FailureKind::TimedOut, _) => Some(
+                    "raise `command_timeout` in config if the command needs more time".to_owned(),
+                ),
+                (FailureKind::Retryable, _) => Some(
+                    "the failure looks temporary, run again or raise `retries` in config"
+                        .to_owned(),
+                ),
+                (_, "ssh" | "scp" | "rsync") => {
+                    Some("`opday doctor` checks connection and docker on hosts".to_owned())
+                }
+                _ => Some("run with `-v` to see executed commands and their output".to_owned()),
+            },
+            OpdayError::Remote { .. } => {
+                Some("`opday doctor` checks connection and docker on hosts".to_owned())
+            }
+            OpdayError::Input(_) => Some("see `opday --help`".to_owned()),
+            OpdayError::Io { .. } => None,
+            OpdayError::Step { step, source } => {
+                let resume = format!("resume with `--from {}`", step);
+                Some(match hint(source.as_ref()) {
+                    Some(hint) => format!("{}; {}", hint, resume),
+                    None => resume,
+                })
+            }
+        }
+    }
+}
+
+impl fmt::Display for OpdayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpdayError::Config(message) | OpdayError::Input(message) => write!(f, "{}", message),
+            OpdayError::Compose { path, message } => {
+                write!(f, "Could not read {}: {}", path.display(), message)
+            }
+            OpdayError::Command(error) => write!(f, "{}", error),
+            OpdayError::Remote { hosts, message } => {
+                write!(f, "{}: {}", hosts.join(", "), message)
+            }
+            OpdayError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            OpdayError::Step { step, source } => write!(f, "Step `{}` failed: {}", step, source),
+        }
+    }
+}
+
+impl Error for OpdayError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            OpdayError::Io { source, .. } => Some(source),
+            OpdayError::Step { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<ExecError> for OpdayError {
+    fn from(error: ExecError) -> OpdayError {
+        OpdayError::Command(error)
+    }
+}
+
+/// Exit code for any error, errors of other kinds are general failures.
+pub fn exit_code(error: &(dyn Error + 'static)) -> u8 {
+    if let Some(error) = error.downcast_ref::<OpdayError>() {
+        return error.exit_code();
+    }
+    match error.is::<io::Error>() {
+        true => EXIT_IO,
+        false => EXIT_FAILURE,
+    }
+}
+
+/// Hint for any error.
+pub fn hint(error: &(dyn Error + 'static)) -> Option<String> {
+    error.downcast_ref::<OpdayError>().and_then(|e| e.hint())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        let error: Box<dyn Error> = Box::new(OpdayError::Config("no hosts".to_owned()));
+        assert_eq!(exit_code(error.as_ref()), EXIT_CONFIG);
+        assert!(hint(error.as_ref())
+            .unwrap()
+            .contains("opday config schema"));
+
+        let error: Box<dyn Error> = Box::new(OpdayError::remote("root@one", "lock is held"));
+        assert_eq!(error.to_string(), "root@one: lock is held");
+        assert_eq!(exit_code(error.as_ref()), EXIT_REMOTE);
+
+        let error: Box<dyn Error> = io::Error::other("disk is full").into();
+        assert_eq!(exit_code(error.as_ref()), EXIT_IO);
+
+        let error: Box<dyn Error> = Box::new(OpdayError::Step {
+            step: "deploy".to_owned(),
+            source: Box::new(OpdayError::remote("root@one", "lock is held")),
+        });
+        assert_eq!(
+            error.to_string(),
+            "Step `deploy` failed: root@one: lock is held"
+        );
+        assert_eq!(exit_code(error.as_ref()), EXIT_REMOTE);
+        assert!(hint(error.as_ref())
+            .unwrap()
+            .ends_with("resume with `--from deploy`"));
+
+        let error: Box<dyn Error> = "something else".into();
+        assert_eq!(exit_code(error.as_ref()), EXIT_FAILURE);
+        assert_eq!(hint(error.as_ref()), None);
+    }
+}
@@ -1,167 +1,768 @@
-use log::debug;
+use log::{debug, warn};
 
-use std::process::{Command, Stdio};
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
-use std::io::Read;
-use std::{thread, time};
+use crate::config::{KnownHosts, Scope};
+use crate::error::OpdayError;
+use crate::output::{self, Event};
+use crate::ui;
 
+use std::io::{BufRead, BufReader, Read};
+use std::thread;
+
+/// Wraps value in single quotes for POSIX shell.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r#"'\''"#))
+}
+
+/// Quotes value for POSIX shell only when it has special characters, so commands stay readable.
+pub fn shell_word(value: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-+=@%:,./".contains(c);
+    match !value.is_empty() && value.chars().all(plain) {
+        true => value.to_owned(),
+        false => shell_quote(value),
+    }
+}
+
+/// Program with arguments joined into a shell command line.
+pub fn shell_join(program: &str, args: &[&str]) -> String {
+    std::iter::once(program)
+        .chain(args.iter().copied())
+        .map(shell_word)
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// `KEY=VALUE` build args as env file for `docker compose --env-file`. Values are single
+/// quoted, so compose neither interpolates `$` nor cuts them at ` #`. Compose has no escape
+/// inside single quotes, so values with `'`, line breaks or a trailing `\` are rejected.
+pub fn env_file_content(build_args: &[String]) -> Result<String, OpdayError> {
+    let mut content = String::new();
+    for build_arg in build_args {
+        let Some((key, value)) = build_arg.split_once('=') else {
+            return Err(OpdayError::Input(format!(
+                "Invalid build-arg without `=`: `{}`",
+                build_arg
+            )));
+        };
+        if value.contains(['\'', '\n', '\r']) || value.ends_with('\\') {
+            return Err(OpdayError::Input(format!(
+                "Build-arg `{}` has a quote, a line break or a trailing backslash, \
+                 env file can't hold it.",
+                key
+            )));
+        }
+        content += &format!("{}='{}'\n", key, value);
+    }
+    Ok(content)
+}
+
+/// Command line for remote shell. Every argument and environment assignment is quoted,
+/// so values with spaces, quotes or `$` reach the program as they are.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RemoteCommand {
+    env: Vec<(String, String)>,
+    args: Vec<String>,
+}
+
+impl RemoteCommand {
+    pub fn new(program: &str) -> RemoteCommand {
+        RemoteCommand {
+            env: vec![],
+            args: vec![program.to_owned()],
+        }
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<str>) -> RemoteCommand {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    pub fn args<I>(mut self, args: I) -> RemoteCommand
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    /// Variable set for the program only, passed with `env` to keep names quoted as well.
+    pub fn env(mut self, key: &str, value: &str) -> RemoteCommand {
+        self.env.push((key.to_owned(), value.to_owned()));
+        self
+    }
+}
+
+impl fmt::Display for RemoteCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let assignments = self
+            .env
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value));
+        let words: Vec<String> = match self.env.is_empty() {
+            true => self.args.iter().map(|arg| shell_word(arg)).collect(),
+            false => std::iter::once("env".to_owned())
+                .chain(assignments.map(|assignment| shell_word(&assignment)))
+                .chain(self.args.iter().map(|arg| shell_word(arg)))
+                .collect(),
+        };
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+/// Seconds multiplexed ssh connection stays open after the last command.
+const CONTROL_PERSIST: u64 = 60;
+
+static CONTROL_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Private directory for sockets of multiplexed ssh connections, shared by runs of the same user.
+fn control_dir() -> Option<&'static PathBuf> {
+    CONTROL_DIR
+        .get_or_init(|| {
+            let user = std::env::var("USER").unwrap_or_else(|_| "user".to_owned());
+            let dir = std::env::temp_dir().join(format!("opday-ssh-{}", user));
+            match fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(&dir)
+            {
+                Ok(()) => Some(dir),
+                Err(e) => {
+                    debug!("Ssh multiplexing is off, could not create {:?}: {}", dir, e);
+                    None
+                }
+            }
+        })
+        .as_ref()
+}
+
+/// Ssh session settings of environment. With multiplexing every host gets one connection
+/// reused by all `ssh`, `scp` and `rsync` calls.
+#[derive(Debug, Clone, Default)]
 pub struct RemoteHostCall {
     pub private_key: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub jump_host: Option<String>,
+    pub known_hosts: Option<KnownHosts>,
+    pub connect_timeout: Option<u64>,
+    pub keepalive_interval: Option<u64>,
+    pub multiplex: bool,
 }
 
-#[allow(dead_code)]
-pub fn execute_short_command(
+impl RemoteHostCall {
+    pub fn new(scope: &Scope) -> RemoteHostCall {
+        RemoteHostCall {
+            private_key: scope.ssh_private_key.clone(),
+            user: scope.ssh_user.clone(),
+            port: scope.ssh_port,
+            jump_host: scope.ssh_jump_host.clone(),
+            known_hosts: scope.ssh_known_hosts,
+            connect_timeout: scope.ssh_connect_timeout,
+            keepalive_interval: scope.ssh_keepalive_interval,
+            multiplex: scope.ssh_multiplex,
+        }
+    }
+
+    /// Options understood by both `ssh` and `scp`.
+    pub fn options(&self) -> Vec<String> {
+        let mut options: Vec<String> = vec![];
+        if let Some(private_key) = &self.private_key {
+            options.push("-i".to_owned());
+            options.push(private_key.clone());
+        }
+        let mut option = |name: &str, value: String| {
+            options.push("-o".to_owned());
+            options.push(format!("{}={}", name, value));
+        };
+        if let Some(user) = &self.user {
+            option("User", user.clone());
+        }
+        if let Some(port) = self.port {
+            option("Port", port.to_string());
+        }
+        if let Some(jump_host) = &self.jump_host {
+            option("ProxyJump", jump_host.clone());
+        }
+        if let Some(known_hosts) = self.known_hosts {
+            let value = match known_hosts {
+                KnownHosts::Strict => "yes",
+                KnownHosts::AcceptNew => "accept-new",
+                KnownHosts::Off => "no",
+            };
+            option("StrictHostKeyChecking", value.to_owned());
+            if known_hosts == KnownHosts::Off {
+                option("UserKnownHostsFile", "/dev/null".to_owned());
+            }
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            option("ConnectTimeout", connect_timeout.to_string());
+        }
+        if let Some(keepalive_interval) = self.keepalive_interval {
+            option("ServerAliveInterval", keepalive_interval.to_string());
+        }
+        if let Some(control_dir) = self.multiplex.then(control_dir).flatten() {
+            option("ControlMaster", "auto".to_owned());
+            option(
+                "ControlPath",
+                control_dir.join("%C").to_string_lossy().into_owned(),
+            );
+            option("ControlPersist", CONTROL_PERSIST.to_string());
+        }
+        options
+    }
+
+    /// Arguments for `ssh` to run `command` on `address`.
+    pub fn ssh_args(&self, address: &str, command: &str, tty: bool) -> Vec<String> {
+        let mut params: Vec<String> = vec![];
+        if tty {
+            params.push("-t".to_owned());
+        }
+        params.extend(self.options());
+        params.push(address.to_owned());
+        params.push(command.to_owned());
+        params
+    }
+
+    /// Arguments for `scp` to copy local file to `remote_path` on `address`.
+    pub fn scp_args(&self, local_path: &str, address: &str, remote_path: &str) -> Vec<String> {
+        let mut params = self.options();
+        params.push(local_path.to_owned());
+        params.push(format!("{}:{}", address, remote_path));
+        params
+    }
+
+    /// Remote shell for `rsync -e`.
+    pub fn rsync_shell(&self) -> String {
+        std::iter::once("ssh".to_owned())
+            .chain(self.options().iter().map(|option| shell_quote(option)))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+fn command_started(program: &str, command: &[&str]) -> Instant {
+    output::emit(Event::CommandStarted {
+        program,
+        args: output::redact(command),
+    });
+    Instant::now()
+}
+
+fn command_finished(program: &str, command: &[&str], status: Option<ExitStatus>, started: Instant) {
+    output::emit(Event::CommandFinished {
+        program,
+        args: output::redact(command),
+        exit_code: status.and_then(|status| status.code()),
+        duration_ms: started.elapsed().as_millis(),
+    });
+}
+
+/// Why command failed, tells whether running it again can help.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// Network hiccups and overloaded registries, the same command can succeed later
+    Retryable,
+    /// Command ran longer than its timeout and was killed
+    TimedOut,
+    /// Everything else, e.g. wrong arguments or denied access
+    Fatal,
+}
+
+impl FailureKind {
+    pub fn is_retryable(self) -> bool {
+        matches!(self, FailureKind::Retryable | FailureKind::TimedOut)
+    }
+}
+
+/// Last lines of stderr shown in error message.
+const STDERR_TAIL_LINES: usize = 10;
+/// Longer commands are cut in error message, `-v` logs them whole.
+const MESSAGE_COMMAND_CHARS: usize = 160;
+
+/// Failed run of external command.
+#[derive(Debug)]
+pub struct ExecError {
+    pub kind: FailureKind,
+    pub program: String,
+    /// Program with arguments, secrets are redacted
+    pub command: String,
+    pub exit_code: Option<i32>,
+    pub stderr: String,
+}
+
+impl ExecError {
+    pub fn stderr_tail(&self) -> Vec<&str> {
+        let lines: Vec<&str> = self
+            .stderr
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .collect();
+        lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].to_vec()
+    }
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let command = match self.command.char_indices().nth(MESSAGE_COMMAND_CHARS) {
+            Some((index, _)) => format!("{}...", &self.command[..index]),
+            None => self.command.clone(),
+        };
+        match (self.kind, self.exit_code) {
+            (FailureKind::TimedOut, _) => write!(f, "`{}` timed out and was killed", command)?,
+            (_, Some(exit_code)) => write!(f, "`{}` failed with exit code {}", command, exit_code)?,
+            (_, None) => write!(f, "`{}` was killed by signal", command)?,
+        }
+        for line in self.stderr_tail() {
+            write!(f, "\n  {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ExecError {}
+
+/// Errors of ssh and registries which are not fixed by running command again.
+const FATAL_PATTERNS: &[&str] = &[
+    "permission denied",
+    "host key verification failed",
+    "could not resolve hostname",
+    "unauthorized",
+    "manifest unknown",
+];
+
+/// Errors of network and registries which usually go away.
+const RETRYABLE_PATTERNS: &[&str] = &[
+    "connection reset",
+    "connection refused",
+    "connection timed out",
+    "connection closed",
+    "broken pipe",
+    "unexpected eof",
+    "i/o timeout",
+    "tls handshake timeout",
+    "temporary failure",
+    "toomanyrequests",
+    "500 internal server error",
+    "502 bad gateway",
+    "503 service unavailable",
+    "504 gateway time",
+];
+
+/// Classifies failure by exit code of ssh-based tools and by error messages.
+pub fn classify(program: &str, exit_code: Option<i32>, stderr: &str) -> FailureKind {
+    let stderr = stderr.to_lowercase();
+    if FATAL_PATTERNS
+        .iter()
+        .any(|pattern| stderr.contains(pattern))
+    {
+        return FailureKind::Fatal;
+    }
+    if RETRYABLE_PATTERNS
+        .iter()
+        .any(|pattern| stderr.contains(pattern))
+    {
+        return FailureKind::Retryable;
+    }
+    match (program, exit_code) {
+        // ssh reports connection errors with 255
+        ("ssh" | "scp" | "rsync", Some(255)) => FailureKind::Retryable,
+        // rsync socket, protocol stream and timeout errors
+        ("rsync", Some(10 | 12 | 30 | 35)) => FailureKind::Retryable,
+        _ => FailureKind::Fatal,
+    }
+}
+
+/// Whether error is failed command worth running again.
+pub fn is_retryable(error: &(dyn std::error::Error + 'static)) -> bool {
+    matches!(
+        error.downcast_ref::<OpdayError>(),
+        Some(OpdayError::Command(error)) if error.kind.is_retryable()
+    )
+}
+
+/// Limits of one command run.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecOptions {
+    /// Command and all processes it started are killed after this time
+    pub timeout: Option<Duration>,
+}
+
+impl ExecOptions {
+    pub fn new(scope: &Scope) -> ExecOptions {
+        ExecOptions {
+            timeout: scope.command_timeout.map(Duration::from_secs),
+        }
+    }
+}
+
+/// Runs idempotent steps again after retryable failures, doubling delay every attempt.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(scope: &Scope) -> RetryPolicy {
+        RetryPolicy {
+            retries: scope.retries,
+            delay: Duration::from_secs(scope.retry_delay),
+        }
+    }
+
+    pub fn run<T>(
+        &self,
+        mut run: impl FnMut() -> Result<T, Box<dyn std::error::Error>>,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        let mut attempt = 0;
+        let mut delay = self.delay;
+        loop {
+            match run() {
+                Err(e) if attempt < self.retries && is_retryable(e.as_ref()) => {
+                    attempt += 1;
+                    warn!(
+                        "{}; retrying in {} ({}/{})",
+                        e,
+                        ui::format_duration(delay),
+                        attempt,
+                        self.retries
+                    );
+                    thread::sleep(delay);
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn command_with_envs(
     program: &str,
-    command: Vec<&str>,
-    build_arg: &Vec<String>,
-) -> Result<String, Box<dyn std::error::Error>> {
+    command: &[&str],
+    build_arg: &[String],
+) -> Result<Command, OpdayError> {
     let mut exec_command = Command::new(program);
-    exec_command.args(command.clone());
+    exec_command.args(command);
     for build_arg_item in build_arg {
-        let parts: Vec<&str> = build_arg_item.splitn(2, '=').collect();
-        if parts.len() < 2 {
-            panic!("Invalid build-arg without `=`: `{}`", build_arg_item)
-        }
-        exec_command.env(parts[0], parts[1]);
+        let Some((key, value)) = build_arg_item.split_once('=') else {
+            return Err(OpdayError::Input(format!(
+                "Invalid build-arg without `=`: `{}`",
+                build_arg_item
+            )));
+        };
+        exec_command.env(key, value);
+    }
+    Ok(exec_command)
+}
+
+/// Variables set for command, secret-looking values are redacted for logs.
+fn logged_envs(command: &Command) -> Vec<String> {
+    let envs: Vec<String> = command
+        .get_envs()
+        .map(|(key, value)| match value {
+            Some(value) => format!("{}={}", key.to_string_lossy(), value.to_string_lossy()),
+            None => key.to_string_lossy().into_owned(),
+        })
+        .collect();
+    output::redact(&envs.iter().map(|env| env.as_str()).collect::<Vec<&str>>())
+}
+
+fn exec_error(
+    kind: Option<FailureKind>,
+    program: &str,
+    command: &[&str],
+    exit_code: Option<i32>,
+    stderr: String,
+) -> Box<dyn std::error::Error> {
+    Box::new(OpdayError::Command(ExecError {
+        kind: kind.unwrap_or_else(|| classify(program, exit_code, &stderr)),
+        program: program.to_owned(),
+        command: shell_join(
+            program,
+            &output::redact(command)
+                .iter()
+                .map(|arg| arg.as_str())
+                .collect::<Vec<&str>>(),
+        ),
+        exit_code,
+        stderr,
+    }))
+}
+
+/// Kills process group of timed out command with everything it started, e.g. ssh of rsync.
+fn kill_tree(pid: u32) {
+    debug!("Killing process group {}", pid);
+    // SAFETY: plain syscall, negative pid addresses process group created at spawn
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+/// Output of finished process.
+struct Finished {
+    status: ExitStatus,
+    stdout: String,
+    stderr: String,
+    timed_out: bool,
+}
+
+/// Runs process collecting its output, `live` output is logged and shown in progress UI.
+fn run_process(
+    exec_command: &mut Command,
+    options: &ExecOptions,
+    live: bool,
+) -> Result<Finished, std::io::Error> {
+    exec_command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    if options.timeout.is_some() {
+        // own process group, so timeout kills the whole tree
+        exec_command.process_group(0);
     }
+    let mut process = exec_command.spawn()?;
+    let pid = process.id();
+
+    let task = ui::current();
+    let stdout = process.stdout.take().unwrap();
+    let stderr = process.stderr.take().unwrap();
+    let timed_out = AtomicBool::new(false);
+    let (done, finished) = mpsc::channel::<()>();
+    let (stdout, stderr, status) = thread::scope(|s| {
+        if let Some(timeout) = options.timeout {
+            let timed_out = &timed_out;
+            s.spawn(move || {
+                if finished.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout) {
+                    timed_out.store(true, Ordering::SeqCst);
+                    kill_tree(pid);
+                }
+            });
+        }
+        let stdout = s.spawn(|| read_lines(stdout, "out", task, live));
+        let stderr = s.spawn(|| read_lines(stderr, "err", task, live));
+        let stdout = stdout.join().unwrap();
+        let stderr = stderr.join().unwrap();
+        let status = process.wait();
+        drop(done);
+        (stdout, stderr, status)
+    });
+    Ok(Finished {
+        status: status?,
+        stdout,
+        stderr,
+        timed_out: timed_out.into_inner(),
+    })
+}
+
+pub fn execute_short_command(
+    program: &str,
+    command: Vec<&str>,
+    build_arg: &[String],
+) -> Result<String, Box<dyn std::error::Error>> {
+    execute_short_command_output(program, command, build_arg).map(|(stdout, _)| stdout)
+}
+
+/// Same as `execute_short_command`, but returns both stdout and stderr.
+pub fn execute_short_command_output(
+    program: &str,
+    command: Vec<&str>,
+    build_arg: &[String],
+) -> Result<(String, String), Box<dyn std::error::Error>> {
+    execute_short_command_with(program, command, build_arg, &ExecOptions::default())
+}
+
+/// Same as `execute_short_command_output` with limits of `options`.
+pub fn execute_short_command_with(
+    program: &str,
+    command: Vec<&str>,
+    build_arg: &[String],
+    options: &ExecOptions,
+) -> Result<(String, String), Box<dyn std::error::Error>> {
+    let mut exec_command = command_with_envs(program, &command, build_arg)?;
 
-    // TODO: correct quoting of command args
-    let command_str = String::new() + program + " " + &command.join(" ");
+    let command_str = shell_join(program, &command);
 
     debug!(
         "Start command: {} envs:{:?}",
         command_str,
-        &exec_command.get_envs(),
+        logged_envs(&exec_command),
     );
-    let output = exec_command.output().expect("failed to execute process");
+    let started = command_started(program, &command);
+    let finished = run_process(&mut exec_command, options, false);
+    command_finished(
+        program,
+        &command,
+        finished.as_ref().ok().map(|f| f.status),
+        started,
+    );
+    let finished = finished?;
 
-    let status = output.status;
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
     debug!(
         "Executed command: {:?} envs:{:?} status: {:?} out: {:?} err: {:?}",
         command_str,
-        &exec_command.get_envs(),
-        status,
-        stdout,
-        stderr
+        logged_envs(&exec_command),
+        finished.status,
+        finished.stdout,
+        finished.stderr
     );
-    if !status.success() {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!(
-                "Command failed: {:?} envs:{:?} status:{:?} stdout:{:?} stderr:{:?}",
-                command_str,
-                &exec_command.get_envs(),
-                status,
-                stdout,
-                stderr
-            ),
-        )));
+    if finished.timed_out || !finished.status.success() {
+        return Err(exec_error(
+            finished.timed_out.then_some(FailureKind::TimedOut),
+            program,
+            &command,
+            finished.status.code(),
+            finished.stderr,
+        ));
+    }
+    Ok((finished.stdout, finished.stderr))
+}
+
+/// Collects output of stream. `live` output is logged and shown in current task of progress UI.
+fn read_lines(reader: impl Read, stream: &str, task: Option<usize>, live: bool) -> String {
+    ui::adopt(task);
+    let mut collected = String::new();
+    for line in BufReader::new(reader).lines().map_while(Result::ok) {
+        if live {
+            debug!("{}: {}", stream, line);
+            ui::line(&line);
+        }
+        collected.push_str(&line);
+        collected.push('\n');
     }
-    Ok(stdout)
+    collected
 }
 
 pub fn execute_command(
     program: &str,
     command: Vec<&str>,
-    build_arg: &Vec<String>,
+    build_arg: &[String],
 ) -> Result<String, Box<dyn std::error::Error>> {
-    let mut exec_command = Command::new(program);
-    exec_command.args(command.clone());
-    for build_arg_item in build_arg {
-        let parts: Vec<&str> = build_arg_item.splitn(2, '=').collect();
-        if parts.len() < 2 {
-            panic!("Invalid build-arg without `=`: `{}`", build_arg_item)
-        }
-        exec_command.env(parts[0], parts[1]);
-    }
+    execute_command_with(program, command, build_arg, &ExecOptions::default())
+}
 
-    // TODO: correct quoting of command args
-    let command_str = String::new() + program + " " + &command.join(" ");
+/// Same as `execute_command` with limits of `options`.
+pub fn execute_command_with(
+    program: &str,
+    command: Vec<&str>,
+    build_arg: &[String],
+    options: &ExecOptions,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut exec_command = command_with_envs(program, &command, build_arg)?;
+
+    let command_str = shell_join(program, &command);
     debug!(
         "Start command: {} envs: {:?} cmd: {}",
         program,
-        &exec_command.get_envs(), // TODO: filter out secrets
+        logged_envs(&exec_command),
         &command_str,
     );
 
-    let mut process = exec_command
+    let started = command_started(program, &command);
+    let finished = run_process(&mut exec_command, options, true);
+    command_finished(
+        program,
+        &command,
+        finished.as_ref().ok().map(|f| f.status),
+        started,
+    );
+    let finished = finished?;
+
+    debug!(
+        "Executed command: {} status: {:?} cmd: {}",
+        program, finished.status, &command_str
+    );
+    if finished.timed_out || !finished.status.success() {
+        return Err(exec_error(
+            finished.timed_out.then_some(FailureKind::TimedOut),
+            program,
+            &command,
+            finished.status.code(),
+            finished.stderr,
+        ));
+    }
+    Ok(finished.stdout)
+}
+
+/// Runs command and passes every line of its stdout and stderr to `on_line` as soon as it's printed.
+pub fn execute_command_lines(
+    program: &str,
+    command: Vec<&str>,
+    on_line: &(dyn Fn(&str) + Sync),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let command_str = shell_join(program, &command);
+    debug!("Start command: {}", &command_str);
+
+    let started = command_started(program, &command);
+    let mut process = Command::new(program)
+        .args(&command)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
-        .spawn()
-        .expect("Failed to start echo process");
+        .spawn()?;
 
+    let task = ui::current();
     let stdout = process.stdout.take().unwrap();
     let stderr = process.stderr.take().unwrap();
-    std::thread::spawn(move || {
-        let mut stdout = stdout;
-        let mut stderr = stderr;
-        let mut closing = false;
-        loop {
-            let mut buffer = [0; 1024];
-            let n = stdout.read(&mut buffer).unwrap();
-            if n == 0 {
-                closing = true;
+    thread::scope(|s| {
+        s.spawn(|| {
+            ui::adopt(task);
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                on_line(&line);
             }
-            if !closing {
-                let s = String::from_utf8_lossy(&buffer[..n]);
-
-                debug!("out: {}", s.replace('\n', "\\n"));
-            }
-
-            let mut buffer = [0; 1024];
-            let n = stderr.read(&mut buffer).unwrap();
-            if closing && n == 0 {
-                debug!("finish-finish");
-                break;
+        });
+        s.spawn(|| {
+            ui::adopt(task);
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                on_line(&line);
             }
-            let s = String::from_utf8_lossy(&buffer[..n]);
-
-            if n != 0 {
-                debug!("err: {}", s.replace('\n', "\\n"));
-            }
-
-            thread::sleep(time::Duration::from_secs(1));
-        }
+        });
     });
 
-    loop {
-        let st = process.try_wait()?;
-        match st {
-            None => debug!("still running"),
-            Some(status) => debug!("exited with: {}", status),
-        }
-        if st.is_some() {
-            break;
-        }
-        thread::sleep(time::Duration::from_secs(1));
+    let status = process.wait()?;
+    command_finished(program, &command, Some(status), started);
+    debug!("Executed command: {} status: {:?}", &command_str, status);
+    if !status.success() {
+        return Err(exec_error(
+            None,
+            program,
+            &command,
+            status.code(),
+            String::new(),
+        ));
     }
+    Ok(())
+}
 
-    let output = exec_command.output().expect("failed to execute process");
+/// Runs command attached to current terminal, e.g. for interactive shells.
+pub fn execute_interactive_command(
+    program: &str,
+    command: Vec<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let command_str = shell_join(program, &command);
+    debug!("Start interactive command: {}", &command_str);
 
-    let status = output.status;
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-    debug!(
-        "Executed command: {} status: {:?} cmd: {}",
-        program, status, &command_str
-    );
+    let started = command_started(program, &command);
+    let status = Command::new(program).args(&command).status()?;
+    command_finished(program, &command, Some(status), started);
+    debug!("Executed command: {} status: {:?}", &command_str, status);
     if !status.success() {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!(
-                "Command failed: {:?} envs:{:?} cmd:{:?} status:{:?} stdout:{:?} stderr:{:?}",
-                program,
-                &exec_command.get_envs(),
-                &command_str,
-                status,
-                stdout,
-                stderr
-            ),
-        )));
-    }
-    Ok("".to_string())
+        return Err(exec_error(
+            None,
+            program,
+            &command,
+            status.code(),
+            String::new(),
+        ));
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -174,4 +775,285 @@ mod tests {
         let _ = execute_command("echo", vec!["hello"], &vec![]).unwrap();
         assert!(true)
     }
+
+    #[rstest]
+    fn test_execute_command_lines() {
+        let lines = std::sync::Mutex::new(vec![]);
+        execute_command_lines("sh", vec!["-c", "echo one; echo two >&2"], &|line| {
+            lines.lock().unwrap().push(line.to_owned())
+        })
+        .unwrap();
+        let mut lines = lines.into_inner().unwrap();
+        lines.sort();
+        assert_eq!(lines, vec!["one", "two"]);
+
+        assert!(execute_command_lines("sh", vec!["-c", "exit 3"], &|_| {}).is_err());
+    }
+
+    #[rstest]
+    fn test_execute_command_timeout() {
+        let options = ExecOptions {
+            timeout: Some(Duration::from_millis(200)),
+        };
+        let started = Instant::now();
+        // background child keeps output open, so whole tree has to be killed
+        let error =
+            execute_command_with("sh", vec!["-c", "sleep 5 & sleep 5"], &[], &options).unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert!(matches!(
+            error.downcast_ref::<OpdayError>(),
+            Some(OpdayError::Command(ExecError {
+                kind: FailureKind::TimedOut,
+                ..
+            }))
+        ));
+
+        let (stdout, _) = execute_short_command_with("echo", vec!["fast"], &[], &options).unwrap();
+        assert_eq!(stdout, "fast\n");
+    }
+
+    #[rstest]
+    #[case(
+        "ssh",
+        Some(255),
+        "ssh: connect to host 10.0.0.5 port 22: Connection refused",
+        FailureKind::Retryable
+    )]
+    #[case(
+        "ssh",
+        Some(255),
+        "root@10.0.0.5: Permission denied (publickey).",
+        FailureKind::Fatal
+    )]
+    #[case("ssh", Some(255), "", FailureKind::Retryable)]
+    #[case(
+        "ssh",
+        Some(1),
+        "Error response from daemon: received unexpected HTTP status: 503 Service Unavailable",
+        FailureKind::Retryable
+    )]
+    #[case("ssh", Some(1), "no such service: backend", FailureKind::Fatal)]
+    #[case(
+        "rsync",
+        Some(12),
+        "rsync error: error in rsync protocol data stream",
+        FailureKind::Retryable
+    )]
+    #[case(
+        "rsync",
+        Some(23),
+        "rsync error: some files could not be transferred",
+        FailureKind::Fatal
+    )]
+    fn test_classify(
+        #[case] program: &str,
+        #[case] exit_code: Option<i32>,
+        #[case] stderr: &str,
+        #[case] expected: FailureKind,
+    ) {
+        assert_eq!(classify(program, exit_code, stderr), expected);
+    }
+
+    #[rstest]
+    fn test_retry_policy() {
+        let retry = RetryPolicy {
+            retries: 2,
+            delay: Duration::ZERO,
+        };
+        let failure = |kind| -> Box<dyn std::error::Error> {
+            Box::new(OpdayError::Command(ExecError {
+                kind,
+                program: "ssh".to_owned(),
+                command: "ssh host docker compose pull".to_owned(),
+                exit_code: Some(255),
+                stderr: String::new(),
+            }))
+        };
+
+        let mut attempts = 0;
+        let result = retry.run(|| {
+            attempts += 1;
+            match attempts {
+                1 | 2 => Err(failure(FailureKind::Retryable)),
+                _ => Ok(attempts),
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let mut attempts = 0;
+        let result: Result<(), _> = retry.run(|| {
+            attempts += 1;
+            Err(failure(FailureKind::Fatal))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+
+        let mut attempts = 0;
+        let result: Result<(), _> = retry.run(|| {
+            attempts += 1;
+            Err(failure(FailureKind::TimedOut))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+    }
+
+    #[rstest]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("/root/app"), "'/root/app'");
+        assert_eq!(shell_quote("it's"), r#"'it'\''s'"#);
+    }
+
+    /// Values the remote shell must not split, expand or execute.
+    const HOSTILE: &[&str] = &[
+        "a b",
+        "$HOME",
+        "$(echo pwned)",
+        "`echo pwned`",
+        "it's",
+        "\"double\"",
+        "; echo pwned",
+        "a\\b",
+        "trailing\\",
+        "*",
+        "new\nline",
+        "",
+        "-n",
+        "~",
+        "#comment",
+        "&& echo pwned |",
+        "${IFS}",
+    ];
+
+    #[rstest]
+    fn test_logged_envs() {
+        let command = command_with_envs(
+            "true",
+            &[],
+            &["TAG=0.0.4".to_owned(), "DB_PASSWORD=hunter2".to_owned()],
+        )
+        .unwrap();
+        let envs = logged_envs(&command);
+        assert!(envs.contains(&"TAG=0.0.4".to_owned()));
+        assert!(
+            !envs.iter().any(|env| env.contains("hunter2")),
+            "{:?}",
+            envs
+        );
+    }
+
+    #[rstest]
+    fn test_shell_word() {
+        assert_eq!(
+            shell_word("/root/app/docker-compose.yaml"),
+            "/root/app/docker-compose.yaml"
+        );
+        assert_eq!(shell_word("BACKEND_TAG=0.0.4"), "BACKEND_TAG=0.0.4");
+        assert_eq!(shell_word(""), "''");
+        assert_eq!(shell_word("/srv/my app"), "'/srv/my app'");
+        assert_eq!(shell_join("echo", &["it's"]), r#"echo 'it'\''s'"#);
+    }
+
+    #[rstest]
+    fn test_env_file_content_hostile() {
+        for value in HOSTILE {
+            let build_args = vec![format!("VALUE={}", value)];
+            let content = env_file_content(&build_args);
+            if value.contains(['\'', '\n']) || value.ends_with('\\') {
+                assert!(matches!(content, Err(OpdayError::Input(_))), "{:?}", value);
+                continue;
+            }
+            let content = content.unwrap();
+            // compose takes everything up to the closing quote as it is
+            assert_eq!(content, format!("VALUE='{}'\n", value));
+        }
+
+        assert_eq!(
+            env_file_content(&["TAG=0.0.4 #1".to_owned(), "PASS=a$b".to_owned()]).unwrap(),
+            "TAG='0.0.4 #1'\nPASS='a$b'\n"
+        );
+        assert!(env_file_content(&["NO_VALUE".to_owned()]).is_err());
+    }
+
+    #[rstest]
+    fn test_remote_command_hostile_args() {
+        let command = RemoteCommand::new("printf")
+            .arg("%s\\0")
+            .args(HOSTILE)
+            .to_string();
+        let output = execute_short_command("sh", vec!["-c", &command], &[]).unwrap();
+        let args: Vec<&str> = output.split('\0').collect();
+        assert_eq!(args[..args.len() - 1], *HOSTILE);
+    }
+
+    #[rstest]
+    fn test_remote_command_hostile_env() {
+        for value in HOSTILE {
+            let command = RemoteCommand::new("printenv")
+                .arg("VALUE")
+                .env("VALUE", value)
+                .to_string();
+            let output = execute_short_command("sh", vec!["-c", &command], &[]).unwrap();
+            assert_eq!(output, value.to_string() + "\n", "{}", command);
+        }
+
+        // name can't break out of assignment either
+        let command = RemoteCommand::new("true")
+            .env("A; echo pwned; B", "1")
+            .to_string();
+        let output = execute_short_command("sh", vec!["-c", &command], &[]).unwrap();
+        assert_eq!(output, "");
+    }
+
+    #[rstest]
+    fn test_ssh_args() {
+        let host = RemoteHostCall {
+            private_key: Some("key".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            host.ssh_args("root@host", "docker ps", true),
+            vec!["-t", "-i", "key", "root@host", "docker ps"]
+        );
+    }
+
+    #[rstest]
+    fn test_session_options() {
+        let host = RemoteHostCall {
+            user: Some("deploy".to_owned()),
+            port: Some(2222),
+            jump_host: Some("me@bastion".to_owned()),
+            known_hosts: Some(KnownHosts::AcceptNew),
+            connect_timeout: Some(5),
+            keepalive_interval: Some(30),
+            ..Default::default()
+        };
+        assert_eq!(
+            host.scp_args("docker.json", "one", "/root/.docker/config.json"),
+            vec![
+                "-o",
+                "User=deploy",
+                "-o",
+                "Port=2222",
+                "-o",
+                "ProxyJump=me@bastion",
+                "-o",
+                "StrictHostKeyChecking=accept-new",
+                "-o",
+                "ConnectTimeout=5",
+                "-o",
+                "ServerAliveInterval=30",
+                "docker.json",
+                "one:/root/.docker/config.json",
+            ]
+        );
+
+        let host = RemoteHostCall {
+            private_key: Some("~/.ssh/key".to_owned()),
+            multiplex: true,
+            ..Default::default()
+        };
+        let shell = host.rsync_shell();
+        assert!(shell.starts_with("ssh '-i' '~/.ssh/key' '-o' 'ControlMaster=auto'"));
+        assert!(shell.ends_with("'-o' 'ControlPersist=60'"));
+    }
 }
@@ -0,0 +1,260 @@
+use log::warn;
+
+use crate::config::{Configuration, Hook, HookStage, Host, Scope};
+use crate::error::OpdayError;
+use crate::exec::{execute_command_lines, shell_quote, RemoteCommand, RemoteHostCall};
+use crate::output;
+use crate::provider::docker::remote_compose_command;
+use crate::ui;
+
+/// Variables available to hook commands: environment, hosts and build args.
+fn variables(scope: &Scope, build_args: &[String], host: Option<&str>) -> Vec<(String, String)> {
+    let mut variables = vec![
+        ("OPDAY_ENV".to_owned(), scope.name.clone()),
+        ("OPDAY_HOSTS".to_owned(), scope.addresses().join(",")),
+    ];
+    if let Some(host) = host {
+        variables.push(("OPDAY_HOST".to_owned(), host.to_owned()));
+    }
+    for build_arg in build_args {
+        if let Some((key, value)) = build_arg.split_once('=') {
+            variables.push((key.to_owned(), value.to_owned()));
+        }
+    }
+    variables
+}
+
+/// Shell command for hook, run locally with `sh -c` or on host over ssh.
+fn hook_command(
+    config: &Configuration,
+    scope: &Scope,
+    hook: &Hook,
+    variables: &[(String, String)],
+) -> String {
+    // hook command itself is shell code from config, so it's the only unquoted part
+    let body = match &hook.service {
+        Some(service) => format!(
+            "{} {}",
+            remote_compose_command(config, scope).args(["run", "--rm", "-T", service]),
+            hook.command
+        ),
+        None if hook.remote => {
+            format!("cd {} && {}", shell_quote(&scope.export_path), hook.command)
+        }
+        None => hook.command.clone(),
+    };
+    variables
+        .iter()
+        .fold(RemoteCommand::new("sh"), |command, (key, value)| {
+            command.env(key, value)
+        })
+        .arg("-c")
+        .arg(body)
+        .to_string()
+}
+
+fn is_remote(hook: &Hook) -> bool {
+    hook.remote || hook.service.is_some()
+}
+
+/// Hosts to run remote hook on. `once` service hook runs on the first host the service is placed on.
+fn hook_hosts<'a>(scope: &'a Scope, hook: &Hook) -> Result<Vec<&'a Host>, OpdayError> {
+    if !hook.once {
+        return Ok(scope.hosts.iter().collect());
+    }
+    let host = match &hook.service {
+        Some(service) => scope
+            .hosts
+            .iter()
+            .find(|host| !scope.placed_services(host, &[service]).is_empty())
+            .ok_or_else(|| {
+                OpdayError::Config(format!(
+                    "Hook `{}` runs in `{}`, but the service is not placed on any host of `{}`.",
+                    hook.command, service, scope.name
+                ))
+            })?,
+        None => match scope.hosts.first() {
+            Some(host) => host,
+            None => return Ok(vec![]),
+        },
+    };
+    Ok(vec![host])
+}
+
+/// Failed local hook keeps exit code and hint of the command, message names the hook
+/// instead of the whole `sh -c` line with variables.
+fn local_hook_error(hook: &Hook, error: Box<dyn std::error::Error>) -> Box<dyn std::error::Error> {
+    match error.downcast::<OpdayError>() {
+        Ok(error) => match *error {
+            OpdayError::Command(mut error) => {
+                error.command = format!("hook {}", hook.command);
+                Box::new(OpdayError::Command(error))
+            }
+            error => Box::new(error),
+        },
+        Err(error) => format!("Hook `{}` failed: {}", hook.command, error).into(),
+    }
+}
+
+/// Runs hooks of the stage in declared order. The first failed hook stops the rest.
+pub fn run_hooks(
+    config: &Configuration,
+    scope: &Scope,
+    stage: HookStage,
+    build_args: &[String],
+    extra: &[(String, String)],
+) -> Result<(), Box<dyn std::error::Error>> {
+    for hook in scope.hooks.iter().filter(|hook| hook.stage == stage) {
+        if !is_remote(hook) {
+            let mut variables = variables(scope, build_args, None);
+            variables.extend(extra.iter().cloned());
+            let command = hook_command(config, scope, hook, &variables);
+            ui::task(&format!("Hook `{}`", hook.command), || {
+                execute_command_lines("sh", vec!["-c", &command], &|line| {
+                    output::line(Some("local"), line)
+                })
+            })
+            .map_err(|e| local_hook_error(hook, e))?;
+            continue;
+        }
+
+        for host in hook_hosts(scope, hook)? {
+            let address = &host.address;
+            let host_scope = scope.for_host(host);
+            let mut variables = variables(scope, build_args, Some(address));
+            variables.extend(extra.iter().cloned());
+            let command = hook_command(config, &host_scope, hook, &variables);
+            let params = RemoteHostCall::new(&host_scope).ssh_args(address, &command, false);
+            let params2: Vec<&str> = params.iter().map(|s| s.as_str()).collect();
+            ui::task(&format!("Hook `{}` on {}", hook.command, address), || {
+                execute_command_lines("ssh", params2, &|line| output::line(Some(address), line))
+            })
+            .map_err(|e| {
+                OpdayError::remote(address, &format!("hook `{}` failed: {}", hook.command, e))
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs `on_failure` hooks after pipeline error. Their own failures are only reported.
+pub fn run_failure_hooks(
+    config: &Configuration,
+    scope: &Scope,
+    build_args: &[String],
+    error: &str,
+) {
+    let extra = [("OPDAY_ERROR".to_owned(), error.to_owned())];
+    if let Err(e) = run_hooks(config, scope, HookStage::OnFailure, build_args, &extra) {
+        warn!("Failure hooks failed: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::read_configuration_raw;
+
+    fn config() -> Configuration {
+        read_configuration_raw(
+            r#"
+            [environments.prod]
+            hosts = ["root@one", "root@two"]
+            export_path = "/root/app"
+            registry = "registry"
+            registry_auth_config = "auth"
+            registry_export_auth_config = "export_auth"
+            docker_compose_overrides = []
+
+            [[environments.prod.hooks]]
+            stage = "pre_deploy"
+            service = "backend"
+            command = "alembic upgrade head"
+            once = true
+
+            [[environments.prod.hooks]]
+            stage = "post_deploy"
+            command = "echo released $BACKEND_TAG"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_hooks() {
+        let config = config();
+        let hooks = &config.environments[0].hooks;
+        assert_eq!(hooks.len(), 2);
+        assert_eq!(hooks[0].stage, HookStage::PreDeploy);
+        assert!(is_remote(&hooks[0]));
+        assert!(!is_remote(&hooks[1]));
+    }
+
+    #[test]
+    fn test_hook_command() {
+        let config = config();
+        let scope = &config.environments[0];
+        let variables = variables(scope, &["BACKEND_TAG=0.0.4".to_owned()], Some("root@one"));
+        assert_eq!(
+            hook_command(&config, scope, &scope.hooks[0], &variables),
+            "env OPDAY_ENV=prod OPDAY_HOSTS=root@one,root@two OPDAY_HOST=root@one \
+             BACKEND_TAG=0.0.4 sh -c "
+                .to_owned()
+                + &shell_quote(&format!(
+                    "{} run --rm -T backend alembic upgrade head",
+                    remote_compose_command(&config, scope)
+                ))
+        );
+    }
+
+    #[test]
+    fn test_hook_hosts_placement() {
+        let config = config();
+        let mut scope = config.environments[0].clone();
+        let addresses = |hosts: Vec<&Host>| -> Vec<String> {
+            hosts.iter().map(|host| host.address.clone()).collect()
+        };
+        assert_eq!(
+            addresses(hook_hosts(&scope, &scope.hooks[0]).unwrap()),
+            ["root@one"]
+        );
+        assert_eq!(
+            addresses(hook_hosts(&scope, &scope.hooks[1]).unwrap()),
+            ["root@one", "root@two"]
+        );
+
+        // backend runs only on hosts with `app` role
+        scope.placement = [("app".to_owned(), vec!["backend".to_owned()])].into();
+        scope.hosts[1].roles = vec!["app".to_owned()];
+        assert_eq!(
+            addresses(hook_hosts(&scope, &scope.hooks[0]).unwrap()),
+            ["root@two"]
+        );
+
+        scope.hosts[1].roles = vec![];
+        assert!(hook_hosts(&scope, &scope.hooks[0]).is_err());
+    }
+
+    #[test]
+    fn test_run_local_hook() {
+        let config = config();
+        let scope = &config.environments[0];
+        assert!(run_hooks(
+            &config,
+            scope,
+            HookStage::PostDeploy,
+            &["BACKEND_TAG=0.0.4".to_owned()],
+            &[]
+        )
+        .is_ok());
+
+        let mut failing = scope.clone();
+        failing.hooks[1].command = "exit 3".to_owned();
+        let error = run_hooks(&config, &failing, HookStage::PostDeploy, &[], &[]).unwrap_err();
+        assert_eq!(error.to_string(), "`hook exit 3` failed with exit code 3");
+        assert_eq!(
+            crate::error::exit_code(error.as_ref()),
+            crate::error::EXIT_COMMAND
+        );
+    }
+}
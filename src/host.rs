@@ -0,0 +1,352 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use clap::Subcommand;
+use log::debug;
+use serde_json::{json, Value};
+
+use crate::config::{Configuration, Scope};
+use crate::error::OpdayError;
+use crate::exec::{execute_short_command, shell_quote, RemoteHostCall};
+use crate::output::{self, Event};
+
+extern crate term;
+
+const REMOTE_SCRIPT_PATH: &str = "/tmp/opday-provision.sh";
+
+/// Idempotent provisioning steps. Every step reports `opday-ok` when nothing
+/// had to be done and `opday-changed` otherwise. Variables are prepended by `make_script`.
+const PROVISION_SCRIPT: &str = r#"
+set -eu
+if [ "$(id -u)" != "0" ]; then
+    exec sudo sh "$0" "$@"
+fi
+export DEBIAN_FRONTEND=noninteractive
+
+ok() { echo "opday-ok: $1"; }
+changed() { echo "opday-changed: $1"; }
+
+if command -v docker >/dev/null 2>&1; then
+    ok "docker engine"
+else
+    if ! command -v curl >/dev/null 2>&1; then
+        apt-get update -qq >/dev/null
+        apt-get install -y -qq curl ca-certificates >/dev/null
+    fi
+    curl -fsSL https://get.docker.com | sh >/dev/null 2>&1
+    changed "docker engine"
+fi
+
+if docker compose version >/dev/null 2>&1; then
+    ok "docker compose plugin"
+else
+    apt-get update -qq >/dev/null
+    apt-get install -y -qq docker-compose-plugin >/dev/null
+    changed "docker compose plugin"
+fi
+
+if command -v systemctl >/dev/null 2>&1; then
+    systemctl enable --now docker >/dev/null 2>&1 || true
+fi
+
+if id "$DEPLOY_USER" >/dev/null 2>&1; then
+    ok "user $DEPLOY_USER"
+else
+    useradd --create-home --shell /bin/bash "$DEPLOY_USER"
+    changed "user $DEPLOY_USER"
+fi
+
+if id -nG "$DEPLOY_USER" | tr ' ' '\n' | grep -qx docker; then
+    ok "docker group membership"
+else
+    usermod -aG docker "$DEPLOY_USER"
+    changed "docker group membership"
+fi
+
+DEPLOY_HOME="$(getent passwd "$DEPLOY_USER" | cut -d: -f6)"
+mkdir -p "$DEPLOY_HOME/.ssh"
+touch "$DEPLOY_HOME/.ssh/authorized_keys"
+if grep -qxF "$PUBLIC_KEY" "$DEPLOY_HOME/.ssh/authorized_keys"; then
+    ok "authorized key"
+else
+    echo "$PUBLIC_KEY" >> "$DEPLOY_HOME/.ssh/authorized_keys"
+    changed "authorized key"
+fi
+chmod 700 "$DEPLOY_HOME/.ssh"
+chmod 600 "$DEPLOY_HOME/.ssh/authorized_keys"
+chown -R "$DEPLOY_USER:" "$DEPLOY_HOME/.ssh"
+
+if [ -d "$EXPORT_PATH" ]; then
+    ok "export path $EXPORT_PATH"
+else
+    mkdir -p "$EXPORT_PATH"
+    chown "$DEPLOY_USER:" "$EXPORT_PATH"
+    changed "export path $EXPORT_PATH"
+fi
+
+if [ "$HARDEN_SSHD" = "1" ]; then
+    SSHD_CONFIG=/etc/ssh/sshd_config.d/50-opday.conf
+    SSHD_EXPECTED="PasswordAuthentication no
+KbdInteractiveAuthentication no
+PermitRootLogin prohibit-password
+PermitEmptyPasswords no"
+    if [ -f "$SSHD_CONFIG" ] && [ "$(cat "$SSHD_CONFIG")" = "$SSHD_EXPECTED" ]; then
+        ok "sshd hardening"
+    else
+        mkdir -p /etc/ssh/sshd_config.d
+        echo "$SSHD_EXPECTED" > "$SSHD_CONFIG"
+        sshd -t
+        systemctl reload ssh >/dev/null 2>&1 || systemctl reload sshd >/dev/null 2>&1 || true
+        changed "sshd hardening"
+    fi
+fi
+"#;
+
+#[derive(Subcommand)]
+pub enum HostCommands {
+    /// Installs docker, creates deploy user and export path on hosts
+    Provision {
+        /// Path to config file
+        #[arg(short, long, value_name = "FILE")]
+        config: Option<PathBuf>,
+
+        /// Environment name
+        #[arg(short = 'e', long = "env", value_name = "NAME", env = "OPDAY_ENV")]
+        environment: Option<String>,
+
+        /// User to create for deploys
+        #[arg(long = "user", value_name = "USER", default_value = "deploy")]
+        deploy_user: String,
+
+        /// Public key to authorize for deploy user (default `<ssh_private_key>.pub`)
+        #[arg(long, value_name = "FILE")]
+        public_key: Option<String>,
+
+        /// Keep sshd configuration untouched
+        #[arg(long, action)]
+        no_harden_sshd: bool,
+    },
+}
+
+pub fn prepare_config(command: &HostCommands) -> Option<PathBuf> {
+    match &command {
+        HostCommands::Provision { config, .. } => config.clone(),
+    }
+}
+
+pub fn host_entrypoint(
+    command: &HostCommands,
+    config: &Configuration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match &command {
+        HostCommands::Provision {
+            environment,
+            deploy_user,
+            public_key,
+            no_harden_sshd,
+            ..
+        } => provision(
+            config,
+            environment.as_deref(),
+            &ProvisionOptions {
+                deploy_user: deploy_user.clone(),
+                public_key: public_key.clone(),
+                harden_sshd: !no_harden_sshd,
+            },
+        ),
+    }
+}
+
+pub struct ProvisionOptions {
+    pub deploy_user: String,
+    pub public_key: Option<String>,
+    pub harden_sshd: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum StepResult {
+    Ok(String),
+    Changed(String),
+}
+
+fn make_script(export_path: &str, options: &ProvisionOptions, public_key: &str) -> String {
+    let mut script = String::from("#!/bin/sh\n");
+    script += &format!("EXPORT_PATH={}\n", shell_quote(export_path));
+    script += &format!("DEPLOY_USER={}\n", shell_quote(&options.deploy_user));
+    script += &format!("PUBLIC_KEY={}\n", shell_quote(public_key.trim()));
+    script += &format!(
+        "HARDEN_SSHD={}\n",
+        if options.harden_sshd { "1" } else { "0" }
+    );
+    script += PROVISION_SCRIPT;
+    script
+}
+
+fn parse_report(output: &str) -> Vec<StepResult> {
+    output
+        .lines()
+        .filter_map(|line| {
+            if let Some(step) = line.strip_prefix("opday-ok: ") {
+                Some(StepResult::Ok(step.to_owned()))
+            } else {
+                line.strip_prefix("opday-changed: ")
+                    .map(|step| StepResult::Changed(step.to_owned()))
+            }
+        })
+        .collect()
+}
+
+fn print_report(address: &str, steps: &[StepResult]) {
+    if !output::is_text() {
+        let steps: Vec<Value> = steps
+            .iter()
+            .map(|step| match step {
+                StepResult::Ok(name) => json!({"name": name, "status": "ok"}),
+                StepResult::Changed(name) => json!({"name": name, "status": "changed"}),
+            })
+            .collect();
+        output::emit(Event::Report {
+            kind: "provision",
+            data: json!({"host": address, "steps": steps}),
+        });
+        return;
+    }
+    println!("{}", address);
+    let mut terminal = term::stdout();
+    for step in steps {
+        let (label, color, name) = match step {
+            StepResult::Ok(name) => ("ok     ", term::color::GREEN, name),
+            StepResult::Changed(name) => ("changed", term::color::YELLOW, name),
+        };
+        match terminal.as_mut() {
+            Some(terminal) => {
+                let _ = write!(terminal, "  ");
+                let _ = terminal.fg(color);
+                let _ = write!(terminal, "{}", label);
+                let _ = terminal.reset();
+                let _ = writeln!(terminal, "  {}", name);
+            }
+            None => println!("  {}  {}", label, name),
+        }
+    }
+}
+
+fn provision_host(
+    config: &Configuration,
+    scope: &Scope,
+    address: &str,
+    script_path: &Path,
+) -> Result<Vec<StepResult>, Box<dyn std::error::Error>> {
+    let host = RemoteHostCall::new(scope);
+    debug!("Provisioning {} from {}", address, config.path);
+
+    {
+        let params = host.scp_args(&script_path.to_string_lossy(), address, REMOTE_SCRIPT_PATH);
+        let params2: Vec<&str> = params.iter().map(|s| s.as_str()).collect();
+        execute_short_command("scp", params2, &[])?;
+    }
+
+    let output = {
+        let command = format!("sh {} && rm -f {}", REMOTE_SCRIPT_PATH, REMOTE_SCRIPT_PATH);
+        let params = host.ssh_args(address, &command, false);
+        let params2: Vec<&str> = params.iter().map(|s| s.as_str()).collect();
+        execute_short_command("ssh", params2, &[])?
+    };
+
+    Ok(parse_report(&output))
+}
+
+pub fn provision(
+    config: &Configuration,
+    environment: Option<&str>,
+    options: &ProvisionOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let scope = config.required_environment(environment)?;
+
+    let public_key_path = match (&options.public_key, &scope.ssh_private_key) {
+        (Some(public_key), _) => public_key.clone(),
+        (None, Some(private_key)) => private_key.clone() + ".pub",
+        (None, None) => {
+            return Err(Box::new(OpdayError::Input(
+                "Public key is required: pass `--public-key` or set `ssh_private_key`.".to_owned(),
+            )))
+        }
+    };
+    let public_key_path = match (public_key_path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => Path::new(&home).join(rest),
+        _ => Path::new(&public_key_path).to_path_buf(),
+    };
+    let public_key =
+        fs::read_to_string(&public_key_path).map_err(|e| OpdayError::io(&public_key_path, e))?;
+
+    let internal_files = Path::new(&config.path).join(".opday-generated");
+    fs::create_dir_all(&internal_files)?;
+    let script_path = internal_files.join("provision.sh");
+
+    let mut failed = vec![];
+    for host in &scope.hosts {
+        let address = &host.address;
+        let host_scope = scope.for_host(host);
+        fs::write(
+            &script_path,
+            make_script(&host_scope.export_path, options, &public_key),
+        )?;
+        match provision_host(config, &host_scope, address, &script_path) {
+            Ok(steps) => print_report(address, &steps),
+            Err(e) => {
+                failed.push(address.clone());
+                output::host_result(address, "failed", &format!("failed: {}", e));
+            }
+        }
+    }
+    let _ = fs::remove_file(&script_path);
+
+    if !failed.is_empty() {
+        return Err(Box::new(OpdayError::Remote {
+            message: format!(
+                "Provisioning failed on {} of {} hosts.",
+                failed.len(),
+                scope.hosts.len()
+            ),
+            hosts: failed,
+        }));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_make_script() {
+        let options = ProvisionOptions {
+            deploy_user: "deploy".to_owned(),
+            public_key: None,
+            harden_sshd: false,
+        };
+        let script = make_script("/srv/my app", &options, "ssh-ed25519 AAAA user@laptop\n");
+        assert!(script.starts_with(
+            "#!/bin/sh
+EXPORT_PATH='/srv/my app'
+DEPLOY_USER='deploy'
+PUBLIC_KEY='ssh-ed25519 AAAA user@laptop'
+HARDEN_SSHD=0
+"
+        ));
+        assert!(script.contains("changed \"docker engine\""));
+    }
+
+    #[test]
+    fn test_parse_report() {
+        let output = "Warning: something\nopday-ok: docker engine\nopday-changed: user deploy\n";
+        assert_eq!(
+            parse_report(output),
+            vec![
+                StepResult::Ok("docker engine".to_owned()),
+                StepResult::Changed("user deploy".to_owned()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,373 @@
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use serde_yaml::value::{Tag, TaggedValue};
+use serde_yaml::{Mapping, Value};
+
+use crate::config::DockerComposeFormat;
+use crate::error::OpdayError;
+use crate::output::{self, Event};
+
+pub struct InitOptions {
+    pub path: PathBuf,
+    pub docker_compose_file: String,
+    pub environment: String,
+    pub registry: Option<String>,
+    pub image_prefix: Option<String>,
+    pub hosts: Vec<String>,
+    pub export_path: Option<String>,
+    pub ssh_private_key: Option<String>,
+    pub force: bool,
+}
+
+/// Variable name for image tag of service, e.g. `BACKEND_TAG` for `backend`.
+fn tag_variable(service: &str) -> String {
+    let name: String = service
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    name + "_TAG"
+}
+
+/// Override for running on hosts: services built locally are pulled from registry instead.
+fn make_run_override(format: &DockerComposeFormat, image_prefix: &str) -> DockerComposeFormat {
+    let mut services = Mapping::new();
+    for (name, service) in format.services.iter() {
+        let Some(name) = name.as_str() else {
+            continue;
+        };
+        let has_build = service
+            .as_mapping()
+            .is_some_and(|service| service.contains_key("build"));
+        if !has_build {
+            continue;
+        }
+
+        let mut service_map = Mapping::new();
+        service_map.insert(
+            Value::String("build".to_owned()),
+            Value::Tagged(Box::new(TaggedValue {
+                tag: Tag::new("reset"),
+                value: Value::Null,
+            })),
+        );
+        service_map.insert(
+            Value::String("image".to_owned()),
+            Value::String(format!(
+                "{}/{}:${{{}}}",
+                image_prefix,
+                name,
+                tag_variable(name)
+            )),
+        );
+        services.insert(Value::String(name.to_owned()), Value::Mapping(service_map));
+    }
+
+    DockerComposeFormat {
+        version: format.version.clone(),
+        services,
+    }
+}
+
+/// Home directory of ssh user on Linux host in `user@address` form.
+fn remote_home(host: &str) -> String {
+    match host.split_once('@') {
+        Some(("root", _)) | None => "/root".to_owned(),
+        Some((user, _)) => format!("/home/{}", user),
+    }
+}
+
+fn quote(value: &str) -> String {
+    toml::Value::String(value.to_owned()).to_string()
+}
+
+fn make_config(
+    options: &InitOptions,
+    registry: &str,
+    hosts: &[String],
+    export_path: &str,
+    override_file: &str,
+) -> String {
+    let mut content = String::new();
+    if options.docker_compose_file != "docker-compose.yaml" {
+        content += &format!(
+            "docker_compose_file = {}\n\n",
+            quote(&options.docker_compose_file)
+        );
+    }
+
+    content += "[environments]\n";
+    if let Some(ssh_private_key) = &options.ssh_private_key {
+        content += &format!("ssh_private_key = {}\n", quote(ssh_private_key));
+    }
+    content += &format!("registry = {}\n", quote(registry));
+    content += &format!(
+        "registry_auth_config = {}\n",
+        quote(".secrets/docker-config.json")
+    );
+    content += &format!(
+        "registry_export_auth_config = {}\n",
+        quote(&format!(
+            "{}/.docker/config.json",
+            remote_home(hosts.first().map(|h| h.as_str()).unwrap_or(""))
+        ))
+    );
+
+    content += &format!("\n[environments.{}]\n", options.environment);
+    content += "hosts = [\n";
+    for host in hosts {
+        content += &format!("    {},\n", quote(host));
+    }
+    content += "]\n\n";
+    content += &format!("export_path = {}\n", quote(export_path));
+    content += &format!(
+        "docker_compose_overrides = [\n    {},\n]\n",
+        quote(override_file)
+    );
+    content
+}
+
+/// Appends missing entries to `.gitignore` content.
+fn append_gitignore(content: &str, entries: &[&str]) -> String {
+    let mut result = content.to_owned();
+    for entry in entries {
+        if content.lines().any(|line| line.trim() == *entry) {
+            continue;
+        }
+        if !result.is_empty() && !result.ends_with('\n') {
+            result += "\n";
+        }
+        result += entry;
+        result += "\n";
+    }
+    result
+}
+
+fn prompt(question: &str, default: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
+    // stdout stays parsable with `--output json`
+    let mut stderr = io::stderr();
+    match default {
+        Some(default) => write!(stderr, "{} [{}]: ", question, default)?,
+        None => write!(stderr, "{}: ", question)?,
+    }
+    stderr.flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    let answer = answer.trim();
+    if answer.is_empty() {
+        if let Some(default) = default {
+            return Ok(default.to_owned());
+        }
+        return Err(OpdayError::Input(format!("{} is required.", question)).into());
+    }
+    Ok(answer.to_owned())
+}
+
+/// Takes value from flag or asks for it when stdin is terminal. Non-interactive runs fall back to default.
+fn value_or_prompt(
+    value: &Option<String>,
+    flag: &str,
+    question: &str,
+    default: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if let Some(value) = value {
+        return Ok(value.clone());
+    }
+    if !io::stdin().is_terminal() {
+        if let Some(default) = default {
+            return Ok(default.to_owned());
+        }
+        return Err(OpdayError::Input(format!(
+            "`{}` is required when stdin is not a terminal.",
+            flag
+        ))
+        .into());
+    }
+    prompt(question, default)
+}
+
+fn report_file(path: &Path, action: &str) {
+    match output::is_text() {
+        true => println!("{} {}", action, path.display()),
+        false => output::emit(Event::File {
+            path: path.display().to_string(),
+            action: &action.to_lowercase(),
+        }),
+    }
+}
+
+fn write_file(path: &Path, content: &str) -> Result<(), Box<dyn std::error::Error>> {
+    fs::write(path, content)?;
+    report_file(path, "Created");
+    Ok(())
+}
+
+pub fn init(options: &InitOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let docker_compose_path = options.path.join(&options.docker_compose_file);
+    let compose_error = |message: String| OpdayError::Compose {
+        path: docker_compose_path.clone(),
+        message,
+    };
+    let content =
+        fs::read_to_string(&docker_compose_path).map_err(|e| compose_error(e.to_string()))?;
+    let format: DockerComposeFormat =
+        serde_yaml::from_str(&content).map_err(|e| compose_error(e.to_string()))?;
+    debug!(
+        "Found services: {:?}",
+        format.services.keys().collect::<Vec<_>>()
+    );
+
+    let project = fs::canonicalize(&options.path)?
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "app".to_owned());
+
+    let registry = value_or_prompt(&options.registry, "--registry", "Registry", None)?;
+    let default_image_prefix = format!("{}/{}", registry, project);
+    let image_prefix = value_or_prompt(
+        &options.image_prefix,
+        "--image-prefix",
+        "Image prefix",
+        Some(&default_image_prefix),
+    )?;
+    let hosts = if options.hosts.is_empty() {
+        let host = value_or_prompt(&None, "--host", "Host (user@address)", None)?;
+        vec![host]
+    } else {
+        options.hosts.clone()
+    };
+    let default_export_path = format!("{}/{}", remote_home(&hosts[0]), project);
+    let export_path = value_or_prompt(
+        &options.export_path,
+        "--export-path",
+        "Export path on hosts",
+        Some(&default_export_path),
+    )?;
+
+    let config_path = options.path.join("opday.toml");
+    let override_file = format!("docker-compose.override-run.{}.yaml", options.environment);
+    let override_path = options.path.join(&override_file);
+    for path in [&config_path, &override_path] {
+        if path.exists() && !options.force {
+            return Err(OpdayError::Input(format!(
+                "File {} already exists. Use `--force` to overwrite it.",
+                path.display()
+            ))
+            .into());
+        }
+    }
+
+    let run_override = make_run_override(&format, &image_prefix);
+    write_file(&override_path, &serde_yaml::to_string(&run_override)?)?;
+    write_file(
+        &config_path,
+        &make_config(options, &registry, &hosts, &export_path, &override_file),
+    )?;
+
+    let gitignore_path = options.path.join(".gitignore");
+    let gitignore = fs::read_to_string(&gitignore_path).unwrap_or_default();
+    let updated_gitignore = append_gitignore(&gitignore, &[".opday-generated", ".secrets"]);
+    if updated_gitignore != gitignore {
+        fs::write(&gitignore_path, updated_gitignore)?;
+        report_file(&gitignore_path, "Updated");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::read_configuration_raw;
+
+    fn options() -> InitOptions {
+        InitOptions {
+            path: PathBuf::from("tests/02_simple-backend-with-database"),
+            docker_compose_file: "docker-compose.yaml".to_owned(),
+            environment: "prod".to_owned(),
+            registry: None,
+            image_prefix: None,
+            hosts: vec![],
+            export_path: None,
+            ssh_private_key: None,
+            force: false,
+        }
+    }
+
+    #[test]
+    fn test_tag_variable() {
+        assert_eq!(tag_variable("backend"), "BACKEND_TAG");
+        assert_eq!(tag_variable("my-worker.1"), "MY_WORKER_1_TAG");
+    }
+
+    #[test]
+    fn test_make_run_override() {
+        let f =
+            fs::File::open("tests/02_simple-backend-with-database/docker-compose.yaml").unwrap();
+        let format: DockerComposeFormat = serde_yaml::from_reader(f).unwrap();
+
+        let run_override = make_run_override(&format, "registry.example.com/app");
+        let content = serde_yaml::to_string(&run_override).unwrap();
+        assert_eq!(
+            content,
+            "version: '3.7'
+services:
+  backend:
+    build: !reset null
+    image: registry.example.com/app/backend:${BACKEND_TAG}
+  nginx:
+    build: !reset null
+    image: registry.example.com/app/nginx:${NGINX_TAG}
+"
+        );
+    }
+
+    #[test]
+    fn test_make_config() {
+        let mut options = options();
+        options.ssh_private_key = Some("~/.ssh/id_rsa".to_owned());
+        let content = make_config(
+            &options,
+            "registry.example.com",
+            &["deploy@127.0.0.1".to_owned()],
+            "/home/deploy/app",
+            "docker-compose.override-run.prod.yaml",
+        );
+
+        let config = read_configuration_raw(&content).unwrap();
+        let scope = &config.environments[0];
+        assert_eq!(scope.name, "prod");
+        assert_eq!(scope.addresses(), vec!["deploy@127.0.0.1"]);
+        assert_eq!(scope.registry, "registry.example.com");
+        assert_eq!(
+            scope.registry_export_auth_config,
+            "/home/deploy/.docker/config.json"
+        );
+        assert_eq!(scope.ssh_private_key, Some("~/.ssh/id_rsa".to_owned()));
+        assert_eq!(
+            scope.docker_compose_overrides,
+            vec!["docker-compose.override-run.prod.yaml"]
+        );
+    }
+
+    #[test]
+    fn test_append_gitignore() {
+        assert_eq!(
+            append_gitignore("", &[".opday-generated", ".secrets"]),
+            ".opday-generated\n.secrets\n"
+        );
+        assert_eq!(
+            append_gitignore("target\n.secrets", &[".opday-generated", ".secrets"]),
+            "target\n.secrets\n.opday-generated\n"
+        );
+    }
+}
@@ -0,0 +1,19 @@
+//! Deployments of docker compose projects to remote hosts over ssh.
+//!
+//! `opday` binary is a thin CLI on top of this crate: [`config::read_configuration`] reads
+//! `opday.toml` and [`provider::docker::DockerProvider`] builds, pushes and deploys images.
+
+pub mod config;
+pub mod doc;
+pub mod doctor;
+pub mod error;
+pub mod exec;
+mod hooks;
+pub mod host;
+pub mod init;
+pub mod lock;
+pub mod output;
+mod pipeline;
+pub mod provider;
+mod release;
+pub mod ui;
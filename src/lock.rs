@@ -0,0 +1,412 @@
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use clap::Subcommand;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::config::{Configuration, Host, Scope};
+use crate::error::OpdayError;
+use crate::exec::{execute_short_command, shell_quote, RemoteHostCall};
+use crate::output::{self, Event};
+use crate::release::{utc_now, STATE_DIR};
+
+const LOCKS_DIR: &str = "locks";
+const ACQUIRED_MARKER: &str = "opday-lock:acquired";
+const HELD_MARKER: &str = "opday-lock:held";
+const SECTION_MARKER: &str = "@@opday:";
+/// Lock taken by deploy on every host of environment.
+pub const DEPLOY_LOCK: &str = "deploy";
+const WAIT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Subcommand)]
+pub enum LockCommands {
+    /// Shows locks held on hosts
+    Status {
+        /// Path to config file
+        #[arg(short, long, value_name = "FILE")]
+        config: Option<PathBuf>,
+
+        /// Environment name
+        #[arg(short = 'e', long = "env", value_name = "NAME", env = "OPDAY_ENV")]
+        environment: Option<String>,
+
+        /// Only this host (all hosts of environment by default)
+        #[arg(long = "host", value_name = "ADDRESS")]
+        host: Option<String>,
+    },
+    /// Removes lock left by interrupted command
+    Break {
+        /// Lock name
+        #[arg(value_name = "NAME", default_value = DEPLOY_LOCK)]
+        name: String,
+
+        /// Path to config file
+        #[arg(short, long, value_name = "FILE")]
+        config: Option<PathBuf>,
+
+        /// Environment name
+        #[arg(short = 'e', long = "env", value_name = "NAME", env = "OPDAY_ENV")]
+        environment: Option<String>,
+
+        /// Only this host (all hosts of environment by default)
+        #[arg(long = "host", value_name = "ADDRESS")]
+        host: Option<String>,
+    },
+}
+
+/// Who holds the lock, stored as JSON in lock file on remote host.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockInfo {
+    pub owner: String,
+    pub pid: u32,
+    pub started_at: String,
+    pub command: String,
+}
+
+impl LockInfo {
+    pub fn new(command: &str) -> LockInfo {
+        let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_owned());
+        let hostname = execute_short_command("hostname", vec![], &[])
+            .map(|h| h.trim().to_owned())
+            .unwrap_or_else(|_| "unknown".to_owned());
+        LockInfo {
+            owner: format!("{}@{}", user, hostname),
+            pid: std::process::id(),
+            started_at: utc_now(),
+            command: command.to_owned(),
+        }
+    }
+}
+
+fn locks_dir(scope: &Scope) -> PathBuf {
+    Path::new(&scope.export_path)
+        .join(STATE_DIR)
+        .join(LOCKS_DIR)
+}
+
+fn lock_path(scope: &Scope, name: &str) -> PathBuf {
+    locks_dir(scope).join(name.to_owned() + ".lock")
+}
+
+/// Creates lock file only if it doesn't exist (`set -C`), otherwise prints current holder.
+fn acquire_command(scope: &Scope, name: &str, info: &LockInfo) -> String {
+    let path = lock_path(scope, name);
+    let path = shell_quote(&path.to_string_lossy());
+    let dir = shell_quote(&locks_dir(scope).to_string_lossy());
+    format!(
+        "mkdir -p {dir} && if (set -C; printf '%s\\n' {info} > {path}) 2>/dev/null; then echo {acquired}; else echo {held}; cat {path}; fi",
+        dir = dir,
+        path = path,
+        info = shell_quote(&serde_json::to_string(info).expect("Could not serialize lock.")),
+        acquired = ACQUIRED_MARKER,
+        held = HELD_MARKER,
+    )
+}
+
+fn release_command(scope: &Scope, name: &str) -> String {
+    format!(
+        "rm -f {}",
+        shell_quote(&lock_path(scope, name).to_string_lossy())
+    )
+}
+
+/// `None` when lock is acquired, otherwise who holds it.
+fn parse_acquire(output: &str) -> Result<Option<LockInfo>, String> {
+    let mut lines = output
+        .lines()
+        .skip_while(|line| line.trim() != ACQUIRED_MARKER && line.trim() != HELD_MARKER);
+    match lines.next().map(|line| line.trim()) {
+        Some(ACQUIRED_MARKER) => Ok(None),
+        Some(_) => {
+            let rest: String = lines.collect::<Vec<&str>>().join("\n");
+            serde_json::from_str(&rest)
+                .map(Some)
+                .map_err(|e| format!("Lock file is broken ({}).", e))
+        }
+        None => Err(format!("Unexpected lock output: {}", output)),
+    }
+}
+
+pub fn describe(info: &LockInfo) -> String {
+    format!(
+        "held by {} (pid {}) since {}: {}",
+        info.owner, info.pid, info.started_at, info.command
+    )
+}
+
+/// Exclusive named lock on remote host, released when dropped.
+pub struct RemoteLock {
+    host: RemoteHostCall,
+    address: String,
+    release_command: String,
+}
+
+impl Drop for RemoteLock {
+    fn drop(&mut self) {
+        let params = self
+            .host
+            .ssh_args(&self.address, &self.release_command, false);
+        let params2: Vec<&str> = params.iter().map(|s| s.as_str()).collect();
+        if let Err(e) = execute_short_command("ssh", params2, &[]) {
+            warn!("Could not release lock on {}: {}", self.address, e);
+        }
+    }
+}
+
+fn run_remote(
+    host: &RemoteHostCall,
+    address: &str,
+    command: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let params = host.ssh_args(address, command, false);
+    let params2: Vec<&str> = params.iter().map(|s| s.as_str()).collect();
+    execute_short_command("ssh", params2, &[])
+}
+
+/// Takes lock `name` on host. If it's held, fails with who holds it or waits with `wait`.
+pub fn acquire(
+    scope: &Scope,
+    address: &str,
+    name: &str,
+    command: &str,
+    wait: bool,
+) -> Result<RemoteLock, Box<dyn std::error::Error>> {
+    let host = RemoteHostCall::new(scope);
+    let mut reported = false;
+    let mut info = LockInfo::new(command);
+    loop {
+        info.started_at = utc_now();
+        let output = run_remote(&host, address, &acquire_command(scope, name, &info))?;
+        let holder = match parse_acquire(&output).map_err(|e| OpdayError::remote(address, &e))? {
+            None => {
+                debug!("Lock `{}` acquired on {}", name, address);
+                return Ok(RemoteLock {
+                    host,
+                    address: address.to_owned(),
+                    release_command: release_command(scope, name),
+                });
+            }
+            Some(holder) => holder,
+        };
+        let message = format!("Lock `{}` on {} is {}.", name, address, describe(&holder));
+        if !wait {
+            return Err(Box::new(OpdayError::remote(
+                address,
+                &(message + " Retry with `--wait` or remove it with `opday lock break`."),
+            )));
+        }
+        if !reported {
+            info!("{} Waiting...", message);
+            reported = true;
+        }
+        thread::sleep(WAIT_INTERVAL);
+    }
+}
+
+/// Takes lock `name` on every host, all or nothing.
+pub fn acquire_all(
+    scope: &Scope,
+    hosts: &[Host],
+    name: &str,
+    command: &str,
+    wait: bool,
+) -> Result<Vec<RemoteLock>, Box<dyn std::error::Error>> {
+    // already taken locks are released on error when dropped
+    hosts
+        .iter()
+        .map(|host| acquire(&scope.for_host(host), &host.address, name, command, wait))
+        .collect()
+}
+
+fn status_command(scope: &Scope) -> String {
+    format!(
+        "for f in {}/*.lock; do [ -e \"$f\" ] && echo \"{}$(basename \"$f\" .lock)\" && cat \"$f\"; done; true",
+        shell_quote(&locks_dir(scope).to_string_lossy()),
+        SECTION_MARKER,
+    )
+}
+
+/// Lock names with their holders from `status_command` output.
+fn parse_status(output: &str) -> Vec<(String, Option<LockInfo>)> {
+    let mut locks: Vec<(String, String)> = vec![];
+    for line in output.lines() {
+        if let Some(name) = line.strip_prefix(SECTION_MARKER) {
+            locks.push((name.to_owned(), String::new()));
+        } else if let Some((_, content)) = locks.last_mut() {
+            content.push_str(line);
+        }
+    }
+    locks
+        .into_iter()
+        .map(|(name, content)| (name, serde_json::from_str(&content).ok()))
+        .collect()
+}
+
+/// Settings of every selected host with its address.
+fn target_hosts(
+    config: &Configuration,
+    environment: Option<&str>,
+    host: &Option<String>,
+) -> Result<Vec<(Scope, String)>, Box<dyn std::error::Error>> {
+    let scope = config.required_environment(environment)?;
+    let hosts: Vec<&Host> = match host {
+        Some(address) => match scope.hosts.iter().find(|host| &host.address == address) {
+            Some(host) => vec![host],
+            None => {
+                return Err(Box::new(OpdayError::Input(format!(
+                    "Host `{}` is not found in `{}` environment.",
+                    address, scope.name
+                ))))
+            }
+        },
+        None => scope.hosts.iter().collect(),
+    };
+    Ok(hosts
+        .into_iter()
+        .map(|host| (scope.for_host(host), host.address.clone()))
+        .collect())
+}
+
+pub fn prepare_config(command: &LockCommands) -> Option<PathBuf> {
+    match &command {
+        LockCommands::Status { config, .. } | LockCommands::Break { config, .. } => config.clone(),
+    }
+}
+
+pub fn lock_entrypoint(
+    command: &LockCommands,
+    config: &Configuration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match &command {
+        LockCommands::Status {
+            environment, host, ..
+        } => {
+            for (scope, address) in &target_hosts(config, environment.as_deref(), host)? {
+                let remote = RemoteHostCall::new(scope);
+                let locks = parse_status(&run_remote(&remote, address, &status_command(scope))?);
+                if !output::is_text() {
+                    output::emit(Event::Report {
+                        kind: "locks",
+                        data: json!({
+                            "host": address,
+                            "locks": locks
+                                .iter()
+                                .map(|(name, holder)| json!({"name": name, "holder": holder}))
+                                .collect::<Vec<_>>(),
+                        }),
+                    });
+                    continue;
+                }
+                if locks.is_empty() {
+                    output::host_result(address, "ok", "no locks");
+                }
+                for (name, holder) in locks {
+                    let message = match holder {
+                        Some(holder) => format!("`{}` {}", name, describe(&holder)),
+                        None => format!("`{}` held, lock file is broken", name),
+                    };
+                    output::host_result(address, "held", &message);
+                }
+            }
+            Ok(())
+        }
+        LockCommands::Break {
+            name,
+            environment,
+            host,
+            ..
+        } => {
+            for (scope, address) in &target_hosts(config, environment.as_deref(), host)? {
+                let remote = RemoteHostCall::new(scope);
+                let path = shell_quote(&lock_path(scope, name).to_string_lossy());
+                let output = run_remote(
+                    &remote,
+                    address,
+                    &format!("cat {path} 2>/dev/null && rm -f {path}; true", path = path),
+                )?;
+                let (status, message) = match serde_json::from_str::<LockInfo>(&output) {
+                    Ok(holder) => (
+                        "removed",
+                        format!("removed `{}` {}", name, describe(&holder)),
+                    ),
+                    Err(_) if output.trim().is_empty() => {
+                        ("not_held", format!("`{}` is not held", name))
+                    }
+                    Err(_) => ("removed", format!("removed `{}`", name)),
+                };
+                output::host_result(address, status, &message);
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::read_configuration_raw;
+
+    fn info() -> LockInfo {
+        LockInfo {
+            owner: "alice@laptop".to_owned(),
+            pid: 42,
+            started_at: "2026-10-19T10:30:15Z".to_owned(),
+            command: "run-job backend alembic upgrade head".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_acquire_command() {
+        let config = read_configuration_raw(
+            r#"
+            [environments.prod]
+            hosts = ["root@one"]
+            export_path = "/root/app"
+            registry = "registry"
+            registry_auth_config = "auth"
+            registry_export_auth_config = "export_auth"
+            docker_compose_overrides = []
+            "#,
+        )
+        .unwrap();
+        let scope = &config.environments[0];
+        let command = acquire_command(scope, "deploy", &info());
+        assert!(command
+            .starts_with(r"mkdir -p '/root/app/.opday/locks' && if (set -C; printf '%s\n' '{"));
+        assert!(command.contains("> '/root/app/.opday/locks/deploy.lock'"));
+        assert_eq!(
+            release_command(scope, "deploy"),
+            "rm -f '/root/app/.opday/locks/deploy.lock'"
+        );
+    }
+
+    #[test]
+    fn test_parse_acquire() {
+        assert_eq!(parse_acquire("opday-lock:acquired\n"), Ok(None));
+        let output = format!(
+            "opday-lock:held\n{}\n",
+            serde_json::to_string(&info()).unwrap()
+        );
+        assert_eq!(parse_acquire(&output), Ok(Some(info())));
+        assert!(parse_acquire("").is_err());
+    }
+
+    #[test]
+    fn test_parse_status() {
+        let output = format!(
+            "@@opday:deploy\n{}\n@@opday:job-backend-123\nbroken\n",
+            serde_json::to_string(&info()).unwrap()
+        );
+        assert_eq!(
+            parse_status(&output),
+            vec![
+                ("deploy".to_owned(), Some(info())),
+                ("job-backend-123".to_owned(), None),
+            ]
+        );
+        assert!(parse_status("").is_empty());
+    }
+}
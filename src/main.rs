@@ -1,119 +1,341 @@
-use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::ExitCode;
 
-use clap::{Parser, Subcommand};
-use config::Configuration;
-use log::debug;
+use clap::{ArgMatches, Command, CommandFactory, FromArgMatches, Parser, Subcommand};
+use log::{debug, Level, LevelFilter};
 
-mod config;
-mod doc;
-mod exec;
-mod provider;
-
-use crate::provider::docker::{docker_entrypoint, prepare_config, DockerProviderCommands};
+use opday::config::{self, Configuration};
+use opday::provider;
+use opday::{doctor, error, host, init, lock, output, ui};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
     /// Sets a custom config file
-    #[arg(short, long, value_name = "FILE")]
+    #[arg(short, long, value_name = "FILE", env = "OPDAY_CONFIG")]
     config: Option<PathBuf>,
 
     /// Identity file (private key) for ssh
     #[arg(short = 'i', long, value_name = "FILE")]
     ssh_private_key: Option<PathBuf>,
 
-    /// Verbose level
-    #[arg(short, long, action = clap::ArgAction::Count)]
+    /// Verbose level: `-v` for debug logs, `-vv` for trace logs
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
     verbose: u8,
 
+    /// Prints only errors and command results
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Output format, machine readable formats print structured events
+    #[arg(long, value_enum, global = true, default_value = "text")]
+    output: output::OutputFormat,
+
     #[command(subcommand)]
     provider: Option<Providers>,
 }
 
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Prints JSON Schema of config file
+    Schema,
+}
+
 #[derive(Subcommand)]
 enum Providers {
-    /// Docker provider
-    Docker {
+    /// Config file tools
+    Config {
         /// Subcommand
         #[command(subcommand)]
-        command: DockerProviderCommands,
+        command: ConfigCommands,
+    },
+    /// Creates opday.toml and run override for existing docker compose project
+    Init {
+        /// Project directory with docker compose file
+        #[arg(long, value_name = "DIR", default_value = ".")]
+        path: PathBuf,
+
+        /// Docker compose file name in project directory
+        #[arg(long, value_name = "FILE", default_value = "docker-compose.yaml")]
+        docker_compose_file: String,
+
+        /// Environment name
+        #[arg(short = 'e', long = "env", value_name = "NAME", default_value = "prod")]
+        environment: String,
+
+        /// Container registry, e.g. `registry.digitalocean.com`
+        #[arg(long, value_name = "REGISTRY")]
+        registry: Option<String>,
+
+        /// Prefix of image names (default `<registry>/<project directory>`)
+        #[arg(long, value_name = "PREFIX")]
+        image_prefix: Option<String>,
+
+        /// Remote host in `user@address` form
+        #[arg(long = "host", value_name = "USER@ADDRESS")]
+        hosts: Vec<String>,
+
+        /// Directory on remote hosts to copy project files to
+        #[arg(long, value_name = "PATH")]
+        export_path: Option<String>,
+
+        /// Identity file (private key) for ssh
+        #[arg(long, value_name = "FILE")]
+        ssh_private_key: Option<String>,
+
+        /// Overwrite existing files
+        #[arg(long, action)]
+        force: bool,
+    },
+    /// Checks local tools and remote hosts before deploy
+    Doctor {
+        /// Sets a custom config file
+        #[arg(short, long, value_name = "FILE")]
+        config: Option<PathBuf>,
+
+        /// Environment name (all environments by default)
+        #[arg(short = 'e', long = "env", value_name = "NAME", env = "OPDAY_ENV")]
+        environment: Option<String>,
+    },
+    /// Shows deployed releases and services on all hosts
+    Status {
+        /// Sets a custom config file
+        #[arg(short, long, value_name = "FILE")]
+        config: Option<PathBuf>,
 
-        /// Names
-        #[arg(value_name = "NAME")]
-        names: Vec<String>,
+        /// Environment name (all environments by default)
+        #[arg(short = 'e', long = "env", value_name = "NAME", env = "OPDAY_ENV")]
+        environment: Option<String>,
 
+        /// Build args to resolve expected images (variables of deployed release by default)
+        #[arg(short, long, value_name = "build-arg")]
+        build_arg: Vec<String>,
+    },
+    /// Locks taken by deploys and jobs on hosts
+    Lock {
+        /// Subcommand
+        #[command(subcommand)]
+        command: lock::LockCommands,
+    },
+    /// Remote hosts management
+    Host {
+        /// Subcommand
+        #[command(subcommand)]
+        command: host::HostCommands,
+    },
+    /// Shows what `apply` would change, for every provider
+    Plan {
         /// Sets a custom config file
         #[arg(short, long, value_name = "FILE")]
         config: Option<PathBuf>,
 
+        /// Environment name
+        #[arg(short = 'e', long = "env", value_name = "NAME", env = "OPDAY_ENV")]
+        environment: Option<String>,
+
+        /// Build args
+        #[arg(short, long, value_name = "build-arg")]
+        build_arg: Vec<String>,
+    },
+    /// Brings environment to the state described by config, for every provider
+    Apply {
+        /// Sets a custom config file
+        #[arg(short, long, value_name = "FILE")]
+        config: Option<PathBuf>,
+
+        /// Environment name
+        #[arg(short = 'e', long = "env", value_name = "NAME", env = "OPDAY_ENV")]
+        environment: Option<String>,
+
         /// Build args
         #[arg(short, long, value_name = "build-arg")]
         build_arg: Vec<String>,
     },
 }
 
-#[allow(clippy::single_match)]
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cli = Cli::parse();
+/// Built-in commands and a subcommand of every provider from registry.
+fn cli_command() -> Command {
+    provider::providers()
+        .iter()
+        .fold(Cli::command(), |command, provider| {
+            command.subcommand(provider.command())
+        })
+}
 
-    env_logger::init();
+/// `RUST_LOG` wins over flags. Default level shows progress messages without log decorations.
+fn init_logger(verbose: u8, quiet: bool) {
+    let mut builder = env_logger::Builder::new();
+    if std::env::var_os("RUST_LOG").is_some() {
+        builder.parse_default_env();
+        builder.init();
+        return;
+    }
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    builder.filter_level(level);
+    if level == LevelFilter::Info {
+        builder.format(|f, record| {
+            ui::suspend();
+            match record.level() {
+                Level::Info => writeln!(f, "{}", record.args()),
+                level => writeln!(f, "{}: {}", level.as_str().to_lowercase(), record.args()),
+            }
+        });
+    }
+    builder.init();
+}
 
-    let default_config_file = Path::new("opday.toml");
+/// Reads configuration and applies root flags which override it.
+fn load_configuration(
+    cli: &Cli,
+    config_files: &[Option<PathBuf>],
+) -> Result<Configuration, Box<dyn std::error::Error>> {
+    let mut config_files = config_files.to_vec();
+    config_files.insert(0, cli.config.clone());
+    let mut config = read_configuration_files(&config_files)?;
+    if let Some(private_key) = &cli.ssh_private_key {
+        config.override_ssh_private_key(&private_key.to_string_lossy());
+    }
+    provider::check_config(&config)?;
+    Ok(config)
+}
 
-    let mut global_config: Option<Configuration> = None;
-    if cli.config.is_some() {
-        debug!("Using config file: {:?}", cli.config);
-        global_config = Some(
-            config::read_configuration(&cli.config.unwrap())
-                .expect("Could not read configuration."),
+/// Reads the most specific passed config file, otherwise discovers the default one.
+fn read_configuration_files(
+    config_files: &[Option<PathBuf>],
+) -> Result<Configuration, Box<dyn std::error::Error>> {
+    if let Some(config) = config_files.iter().rev().flatten().next() {
+        debug!("Using config file: {:?}", config);
+        return config::read_configuration(config);
+    }
+
+    if let Some(default_config_file) = config::discover_configuration(&std::env::current_dir()?) {
+        debug!(
+            "Using default config file: {}",
+            default_config_file.display()
         );
+        return config::read_configuration(&default_config_file);
     }
 
+    config::read_configuration_raw("")
+}
+
+fn main() -> ExitCode {
+    let matches = cli_command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    init_logger(cli.verbose, cli.quiet);
+    output::init(cli.output);
+    ui::init(
+        output::is_text()
+            && !cli.quiet
+            && cli.verbose == 0
+            && std::env::var_os("RUST_LOG").is_none(),
+    );
+
+    let result = run(&cli, &matches);
+    output::finish(&result);
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            ui::suspend();
+            eprintln!("error: {}", e);
+            if let Some(hint) = error::hint(e.as_ref()) {
+                eprintln!("hint: {}", hint);
+            }
+            ExitCode::from(error::exit_code(e.as_ref()))
+        }
+    }
+}
+
+fn run(cli: &Cli, matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
     match &cli.provider {
-        Some(Providers::Docker {
-            command,
-            names,
+        Some(Providers::Init {
+            path,
+            docker_compose_file,
+            environment,
+            registry,
+            image_prefix,
+            hosts,
+            export_path,
+            ssh_private_key,
+            force,
+        }) => {
+            init::init(&init::InitOptions {
+                path: path.clone(),
+                docker_compose_file: docker_compose_file.clone(),
+                environment: environment.clone(),
+                registry: registry.clone(),
+                image_prefix: image_prefix.clone(),
+                hosts: hosts.clone(),
+                export_path: export_path.clone(),
+                ssh_private_key: ssh_private_key.clone(),
+                force: *force,
+            })?;
+        }
+        Some(Providers::Doctor {
             config,
+            environment,
+        }) => {
+            let global_config = load_configuration(cli, std::slice::from_ref(config))?;
+            doctor::doctor(&global_config, environment.as_deref())?;
+        }
+        Some(Providers::Status {
+            config,
+            environment,
             build_arg,
         }) => {
-            if config.is_some() {
-                debug!("Using config file: {:?}", config);
-                global_config = Some(
-                    config::read_configuration(
-                        &<std::option::Option<PathBuf> as Clone>::clone(config).unwrap(),
-                    )
-                    .expect("Could not read configuration."),
-                );
+            let global_config = load_configuration(cli, std::slice::from_ref(config))?;
+            for provider in provider::providers() {
+                provider.status(&global_config, environment.as_deref(), build_arg)?;
             }
-
-            let config_after_subsubcommand = prepare_config(command);
-            if config_after_subsubcommand.is_some() {
-                debug!("Using config file: {:?}", config_after_subsubcommand);
-                global_config = Some(
-                    config::read_configuration(&config_after_subsubcommand.unwrap())
-                        .expect("Could not read configuration."),
-                );
+        }
+        Some(Providers::Host { command }) => {
+            let global_config = load_configuration(cli, &[host::prepare_config(command)])?;
+            host::host_entrypoint(command, &global_config)?;
+        }
+        Some(Providers::Lock { command }) => {
+            let global_config = load_configuration(cli, &[lock::prepare_config(command)])?;
+            lock::lock_entrypoint(command, &global_config)?;
+        }
+        Some(Providers::Config {
+            command: ConfigCommands::Schema,
+        }) => {
+            println!("{}", config::configuration_schema());
+        }
+        Some(Providers::Plan {
+            config,
+            environment,
+            build_arg,
+        }) => {
+            let global_config = load_configuration(cli, std::slice::from_ref(config))?;
+            for provider in provider::providers() {
+                provider.plan(&global_config, environment.as_deref(), build_arg)?;
             }
-
-            if global_config.is_none() && Path::exists(default_config_file) {
-                debug!(
-                    "Using default config file: {}",
-                    default_config_file.display()
-                );
-                global_config = Some(
-                    config::read_configuration(default_config_file)
-                        .expect("Could not read configuration."),
-                );
+        }
+        Some(Providers::Apply {
+            config,
+            environment,
+            build_arg,
+        }) => {
+            let global_config = load_configuration(cli, std::slice::from_ref(config))?;
+            for provider in provider::providers() {
+                provider.apply(&global_config, environment.as_deref(), build_arg)?;
             }
-
-            if global_config.is_none() {
-                global_config = Some(config::read_configuration_raw("")?);
+        }
+        None => {
+            if let Some((name, matches)) = matches.subcommand() {
+                if let Some(provider) = provider::find(name) {
+                    let global_config = load_configuration(cli, &[provider.config_file(matches)])?;
+                    provider.run(&global_config, matches)?;
+                }
             }
-            let global_config_unwrap = global_config.unwrap();
-
-            let _ = docker_entrypoint(command, names, &global_config_unwrap, build_arg);
         }
-        _ => {}
     }
 
     Ok(())
@@ -134,10 +356,40 @@ mod tests {
         case::config_after_sub_sub_command_plus_build_arg(vec!["", "docker", "build", "--config", "myconfig", "--build-arg", "BACKEND_TAG=0.0.1"]),
         case::build_push(vec!["", "docker", "build-push"]),
         case::build_push_deploy(vec!["", "docker", "build-push-deploy"]),
+        case::build_push_deploy_from(vec!["", "docker", "build-push-deploy", "--from", "push"]),
         case::login_username(vec!["", "docker", "login", "-u", "username"]),
         case::login_username_password_stdin(vec!["", "docker", "login", "-u", "username", "--password-stdin"]),
+        case::config_schema(vec!["", "config", "schema"]),
+        case::verbose(vec!["", "-vv", "docker", "build"]),
+        case::verbose_after(vec!["", "docker", "deploy", "-v"]),
+        case::quiet(vec!["", "docker", "build-push-deploy", "-q"]),
+        case::ssh_private_key(vec!["", "-i", "~/.ssh/deploy", "docker", "deploy"]),
+        case::output_json(vec!["", "--output", "json", "docker", "build"]),
+        case::output_ndjson_after(vec!["", "docker", "deploy", "--output", "ndjson"]),
+        case::doctor(vec!["", "doctor", "--env", "prod"]),
+        case::status_json(vec!["", "--output", "json", "status"]),
+        case::logs(vec!["", "docker", "logs", "-f", "--since", "10m", "--env", "prod", "backend"]),
+        case::ps_host(vec!["", "docker", "ps", "--host", "root@127.0.0.1"]),
+        case::diff(vec!["", "docker", "diff", "--env", "prod", "--host", "root@host"]),
+        case::exec(vec!["", "docker", "exec", "--env", "prod", "backend", "ls", "-la"]),
+        case::run_job(vec!["", "docker", "run-job", "--env", "prod", "backend", "alembic", "upgrade", "head"]),
+        case::run(vec!["", "docker", "run", "backend", "alembic", "upgrade", "head"]),
+        case::down(vec!["", "docker", "down", "-e", "prod"]),
+        case::host_provision(vec!["", "host", "provision", "--env", "prod", "--user", "deploy"]),
+        case::init(vec!["", "init", "--registry", "registry", "--host", "root@127.0.0.1", "--export-path", "/root/app"]),
+        case::deploy_env(vec!["", "docker", "deploy", "--env", "prod"]),
+        case::deploy_sync(vec!["", "docker", "deploy", "--delete", "--checksum"]),
+        case::deploy_wait(vec!["", "docker", "build-push-deploy", "--wait"]),
+        case::lock_status(vec!["", "lock", "status", "--env", "prod"]),
+        case::lock_break(vec!["", "lock", "break", "deploy", "--host", "root@127.0.0.1"]),
+        case::login_env(vec!["", "docker", "login", "-f", "docker.json", "-e", "prod"]),
+        case::plan(vec!["", "plan", "--env", "prod"]),
+        case::apply_config(vec!["", "apply", "-c", "opday.toml"]),
+        case::plan_build_arg(vec!["", "plan", "--build-arg", "VERSION=1.2.0"]),
+        case::apply_build_arg(vec!["", "apply", "-e", "prod", "-b", "VERSION=1.2.0"]),
     )]
     fn test_config_for_any_order(args: Vec<&str>) {
-        assert!(Cli::try_parse_from(args).is_ok());
+        let matches = cli_command().try_get_matches_from(args).unwrap();
+        assert!(Cli::from_arg_matches(&matches).is_ok());
     }
 }
@@ -0,0 +1,239 @@
+use std::sync::{Mutex, OnceLock};
+
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::release::utc_now;
+use crate::ui;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable output
+    #[default]
+    Text,
+    /// One JSON document with all events printed at exit
+    Json,
+    /// JSON event per line as soon as it happens
+    Ndjson,
+}
+
+static FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+static EVENTS: Mutex<Vec<Value>> = Mutex::new(vec![]);
+
+/// Output format version, changed only on incompatible changes of events.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Values of these arguments and of `KEY=VALUE` pairs with such keys are not printed.
+const SECRET_ARGS: &[&str] = &["--password"];
+const SECRET_KEYS: &[&str] = &["PASSWORD", "SECRET", "TOKEN", "KEY", "AUTH"];
+const REDACTED: &str = "***";
+
+/// Structured events printed instead of free-form text with `--output json|ndjson`.
+///
+/// Schema is documented in `src/doc/output.md`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    StepStarted {
+        step: &'a str,
+    },
+    StepFinished {
+        step: &'a str,
+        status: &'a str,
+        duration_ms: u128,
+    },
+    CommandStarted {
+        program: &'a str,
+        args: Vec<String>,
+    },
+    CommandFinished {
+        program: &'a str,
+        args: Vec<String>,
+        exit_code: Option<i32>,
+        duration_ms: u128,
+    },
+    Output {
+        host: Option<&'a str>,
+        line: &'a str,
+    },
+    Image {
+        service: &'a str,
+        image: &'a str,
+        digest: Option<&'a str>,
+    },
+    Release {
+        id: &'a str,
+        kind: &'a str,
+        environment: &'a str,
+        host: &'a str,
+    },
+    HostResult {
+        host: &'a str,
+        status: &'a str,
+        message: String,
+    },
+    File {
+        path: String,
+        action: &'a str,
+    },
+    Report {
+        kind: &'a str,
+        data: Value,
+    },
+}
+
+pub fn init(format: OutputFormat) {
+    let _ = FORMAT.set(format);
+}
+
+pub fn format() -> OutputFormat {
+    FORMAT.get().copied().unwrap_or_default()
+}
+
+/// Free-form text is printed only in text mode, machine formats keep stdout parsable.
+pub fn is_text() -> bool {
+    format() == OutputFormat::Text
+}
+
+pub fn emit(event: Event) {
+    if is_text() {
+        return;
+    }
+    let mut value = serde_json::to_value(&event).expect("Could not serialize event.");
+    value["timestamp"] = Value::String(utc_now());
+    match format() {
+        OutputFormat::Ndjson => println!("{}", value),
+        _ => EVENTS.lock().unwrap().push(value),
+    }
+}
+
+/// Prints line of command output: prefixed by host in text mode or as `output` event.
+/// Inside progress task the line goes to the task's tail.
+pub fn line(host: Option<&str>, line: &str) {
+    let text = match (is_text(), host) {
+        (true, Some(host)) => format!("{} | {}", host, line),
+        (true, None) => line.to_owned(),
+        (false, _) => return emit(Event::Output { host, line }),
+    };
+    if !ui::line(&text) {
+        ui::println(&text);
+    }
+}
+
+/// Prints `host: message` in text mode or `host_result` event.
+pub fn host_result(host: &str, status: &str, message: &str) {
+    match is_text() {
+        true => ui::println(&format!("{}: {}", host, message)),
+        false => emit(Event::HostResult {
+            host,
+            status,
+            message: message.to_owned(),
+        }),
+    }
+}
+
+/// Final document in `json` mode and final event in `ndjson` mode.
+pub fn finish(result: &Result<(), Box<dyn std::error::Error>>) {
+    let error = result.as_ref().err().map(|e| e.to_string());
+    let status = if error.is_some() { "error" } else { "ok" };
+    match format() {
+        OutputFormat::Text => {}
+        OutputFormat::Ndjson => println!(
+            "{}",
+            json!({
+                "event": "finished",
+                "status": status,
+                "error": error,
+                "timestamp": utc_now(),
+            })
+        ),
+        OutputFormat::Json => {
+            let events: Vec<Value> = std::mem::take(&mut EVENTS.lock().unwrap());
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&json!({
+                    "schema_version": SCHEMA_VERSION,
+                    "status": status,
+                    "error": error,
+                    "events": events,
+                }))
+                .expect("Could not serialize output.")
+            );
+        }
+    }
+}
+
+fn is_secret_key(key: &str) -> bool {
+    let key = key.to_uppercase();
+    SECRET_KEYS.iter().any(|secret| key.contains(secret))
+}
+
+/// Command arguments safe to print: passwords and secret-looking variables are replaced.
+pub fn redact(args: &[&str]) -> Vec<String> {
+    let mut redacted = vec![];
+    let mut hide_next = false;
+    for arg in args {
+        if hide_next {
+            redacted.push(REDACTED.to_owned());
+            hide_next = false;
+            continue;
+        }
+        hide_next = SECRET_ARGS.contains(arg);
+        match arg.split_once('=') {
+            Some((key, _)) if !key.starts_with('-') && is_secret_key(key) => {
+                redacted.push(format!("{}={}", key, REDACTED))
+            }
+            Some((key, _)) if SECRET_ARGS.contains(&key) => {
+                redacted.push(format!("{}={}", key, REDACTED))
+            }
+            _ => redacted.push(arg.to_string()),
+        }
+    }
+    redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(
+            redact(&[
+                "login",
+                "-u",
+                "user",
+                "--password",
+                "hunter2",
+                "--password=hunter2",
+                "API_TOKEN=abc",
+                "BACKEND_TAG=0.0.4",
+            ]),
+            vec![
+                "login",
+                "-u",
+                "user",
+                "--password",
+                "***",
+                "--password=***",
+                "API_TOKEN=***",
+                "BACKEND_TAG=0.0.4",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_event_schema() {
+        let value = serde_json::to_value(Event::StepFinished {
+            step: "push",
+            status: "succeeded",
+            duration_ms: 1200,
+        })
+        .unwrap();
+        assert_eq!(
+            value,
+            json!({"event": "step_finished", "step": "push", "status": "succeeded", "duration_ms": 1200})
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use crate::error::OpdayError;
+use crate::output::{self, Event};
+use crate::ui;
+
+type StepFn<'a> = Box<dyn FnOnce() -> Result<(), Box<dyn Error>> + 'a>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepStatus {
+    Succeeded,
+    Failed,
+    /// Before `--from` step
+    Skipped,
+    /// After failed step
+    NotRun,
+}
+
+#[derive(Debug)]
+pub struct StepReport {
+    pub name: &'static str,
+    pub status: StepStatus,
+    pub duration: Duration,
+}
+
+/// Named steps run one after another. The first failed step stops the pipeline.
+pub struct Pipeline<'a> {
+    steps: Vec<(&'static str, StepFn<'a>)>,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new() -> Pipeline<'a> {
+        Pipeline { steps: vec![] }
+    }
+
+    pub fn step(
+        mut self,
+        name: &'static str,
+        run: impl FnOnce() -> Result<(), Box<dyn Error>> + 'a,
+    ) -> Pipeline<'a> {
+        self.steps.push((name, Box::new(run)));
+        self
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.steps.iter().map(|(name, _)| *name).collect()
+    }
+
+    /// Runs steps starting from `from` and returns reports of all steps with the first error.
+    pub fn run(self, from: Option<&str>) -> (Vec<StepReport>, Result<(), Box<dyn Error>>) {
+        let names = self.names();
+        let start = match from {
+            Some(from) => match names.iter().position(|name| *name == from) {
+                Some(position) => position,
+                None => {
+                    return (
+                        vec![],
+                        Err(Box::new(OpdayError::Input(format!(
+                            "Unknown step `{}`, expected one of: {}.",
+                            from,
+                            names.join(", ")
+                        )))),
+                    )
+                }
+            },
+            None => 0,
+        };
+
+        let mut reports = vec![];
+        let mut result = Ok(());
+        for (index, (name, run)) in self.steps.into_iter().enumerate() {
+            let status = if index < start {
+                StepStatus::Skipped
+            } else if result.is_err() {
+                StepStatus::NotRun
+            } else {
+                output::emit(Event::StepStarted { step: name });
+                let started = Instant::now();
+                let step_result = ui::task(&format!("Step `{}`", name), run);
+                output::emit(Event::StepFinished {
+                    step: name,
+                    status: if step_result.is_ok() {
+                        "succeeded"
+                    } else {
+                        "failed"
+                    },
+                    duration_ms: started.elapsed().as_millis(),
+                });
+                reports.push(StepReport {
+                    name,
+                    status: if step_result.is_ok() {
+                        StepStatus::Succeeded
+                    } else {
+                        StepStatus::Failed
+                    },
+                    duration: started.elapsed(),
+                });
+                if let Err(e) = step_result {
+                    result = Err(Box::new(OpdayError::Step {
+                        step: name.to_owned(),
+                        source: e,
+                    }) as Box<dyn Error>);
+                }
+                continue;
+            };
+            reports.push(StepReport {
+                name,
+                status,
+                duration: Duration::ZERO,
+            });
+        }
+        (reports, result)
+    }
+}
+
+pub fn print_summary(reports: &[StepReport]) {
+    if !output::is_text() {
+        return;
+    }
+    let width = reports.iter().map(|r| r.name.len()).max().unwrap_or(0);
+    for report in reports {
+        let status = match report.status {
+            StepStatus::Succeeded => "ok",
+            StepStatus::Failed => "failed",
+            StepStatus::Skipped => "skipped",
+            StepStatus::NotRun => "not run",
+        };
+        match report.status {
+            StepStatus::Succeeded | StepStatus::Failed => println!(
+                "{:width$}  {:7}  {:.1}s",
+                report.name,
+                status,
+                report.duration.as_secs_f64(),
+                width = width
+            ),
+            _ => println!("{:width$}  {}", report.name, status, width = width),
+        }
+    }
+}
+
+/// Runs pipeline and prints summary of steps.
+pub fn run_pipeline(pipeline: Pipeline, from: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let (reports, result) = pipeline.run(from);
+    print_summary(&reports);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_pipeline_short_circuits() {
+        let calls = RefCell::new(vec![]);
+        let (reports, result) = Pipeline::new()
+            .step("build", || {
+                calls.borrow_mut().push("build");
+                Ok(())
+            })
+            .step("push", || Err("registry is down".into()))
+            .step("deploy", || {
+                calls.borrow_mut().push("deploy");
+                Ok(())
+            })
+            .run(None);
+
+        assert_eq!(*calls.borrow(), vec!["build"]);
+        let statuses: Vec<StepStatus> = reports.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                StepStatus::Succeeded,
+                StepStatus::Failed,
+                StepStatus::NotRun
+            ]
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Step `push` failed: registry is down"
+        );
+    }
+
+    #[test]
+    fn test_pipeline_from() {
+        let calls = RefCell::new(vec![]);
+        let pipeline = || {
+            Pipeline::new()
+                .step("build", || {
+                    calls.borrow_mut().push("build");
+                    Ok(())
+                })
+                .step("push", || {
+                    calls.borrow_mut().push("push");
+                    Ok(())
+                })
+        };
+
+        let (reports, result) = pipeline().run(Some("push"));
+        assert!(result.is_ok());
+        assert_eq!(*calls.borrow(), vec!["push"]);
+        assert_eq!(reports[0].status, StepStatus::Skipped);
+
+        let (_, result) = pipeline().run(Some("deploy"));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Unknown step `deploy`, expected one of: build, push."
+        );
+    }
+}
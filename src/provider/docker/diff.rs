@@ -0,0 +1,486 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::{debug, info};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use similar::TextDiff;
+
+use crate::config::{Configuration, DockerComposeFormat, Host, Scope};
+use crate::error::OpdayError;
+use crate::exec::{execute_short_command, shell_quote, RemoteHostCall};
+use crate::output::{self, Event};
+
+use super::ops::{select_hosts, target_scope, RemoteTarget};
+use super::{
+    generated_run_file, remote_compose_command, write_generated_files_into, GENERATED_DIR,
+    GENERATED_ENV_FILE,
+};
+
+const SECTION_MARKER: &str = "@@opday:";
+
+/// Files deploy relies on, relative to both `path` and `export_path`.
+fn tracked_files(config: &Configuration, scope: &Scope) -> Vec<String> {
+    let mut files = vec![config.docker_compose_file.clone()];
+    files.extend(scope.docker_compose_overrides.iter().cloned());
+    for generated in [generated_run_file(scope).as_str(), GENERATED_ENV_FILE] {
+        files.push(
+            Path::new(GENERATED_DIR)
+                .join(generated)
+                .to_string_lossy()
+                .into_owned(),
+        );
+    }
+    files
+}
+
+/// Local copy of tracked file, generated ones are taken from `generated` directory.
+fn local_file(config: &Configuration, generated: &Path, file: &str) -> PathBuf {
+    match Path::new(file).strip_prefix(GENERATED_DIR) {
+        Ok(name) => generated.join(name),
+        Err(_) => Path::new(&config.path).join(file),
+    }
+}
+
+fn sha256_hex(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// Parses `sha256sum` output into file to checksum map.
+fn parse_sha256sum(output: &str) -> BTreeMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (hash, file) = line.split_once(char::is_whitespace)?;
+            Some((
+                file.trim_start_matches([' ', '*']).to_owned(),
+                hash.to_owned(),
+            ))
+        })
+        .collect()
+}
+
+fn split_sections(output: &str) -> BTreeMap<String, String> {
+    let mut sections: BTreeMap<String, String> = BTreeMap::new();
+    let mut current: Option<String> = None;
+    for line in output.lines() {
+        if let Some(name) = line.strip_prefix(SECTION_MARKER) {
+            current = Some(name.to_owned());
+            sections.entry(name.to_owned()).or_default();
+            continue;
+        }
+        if let Some(name) = &current {
+            let section = sections.entry(name.clone()).or_default();
+            section.push_str(line);
+            section.push('\n');
+        }
+    }
+    sections
+}
+
+/// Remote paths in `docker compose config` point to `export_path`, local ones to project dir.
+fn normalize_remote_config(output: &str, export_path: &str, local_path: &str) -> String {
+    let export_path = export_path.trim_end_matches('/');
+    if export_path.is_empty() {
+        return output.to_owned();
+    }
+    output.replace(export_path, local_path.trim_end_matches('/'))
+}
+
+fn unified_diff(local: &str, remote: &str, local_name: &str, remote_name: &str) -> String {
+    TextDiff::from_lines(local, remote)
+        .unified_diff()
+        .context_radius(3)
+        .header(local_name, remote_name)
+        .to_string()
+}
+
+/// Project name of deploy, compose derives it from the last part of `export_path`.
+/// Both sides of diff use it, so a differently named local directory is not a drift.
+fn project_name(scope: &Scope) -> String {
+    let name: String = Path::new(&scope.export_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .collect();
+    match name.is_empty() {
+        true => "opday".to_owned(),
+        false => name,
+    }
+}
+
+fn local_compose_args(config: &Configuration, scope: &Scope, generated: &Path) -> Vec<String> {
+    let path = Path::new(&config.path);
+    let mut args: Vec<String> = vec![
+        "compose".to_owned(),
+        "--project-name".to_owned(),
+        project_name(scope),
+        "--env-file".to_owned(),
+    ];
+    args.push(
+        generated
+            .join(GENERATED_ENV_FILE)
+            .to_string_lossy()
+            .into_owned(),
+    );
+    for file in [&config.docker_compose_file]
+        .into_iter()
+        .chain(scope.docker_compose_overrides.iter())
+    {
+        args.push("-f".to_owned());
+        args.push(path.join(file).to_string_lossy().into_owned());
+    }
+    args.push("-f".to_owned());
+    args.push(
+        generated
+            .join(generated_run_file(scope))
+            .to_string_lossy()
+            .into_owned(),
+    );
+    args.push("config".to_owned());
+    args
+}
+
+fn local_compose_config(
+    config: &Configuration,
+    scope: &Scope,
+    generated: &Path,
+    build_args: &[String],
+) -> Result<String, Box<dyn std::error::Error>> {
+    let args = local_compose_args(config, scope, generated);
+    let args2: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    execute_short_command("docker", args2, build_args)
+}
+
+fn run_remote(
+    host: &RemoteHostCall,
+    address: &str,
+    command: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let params = host.ssh_args(address, command, false);
+    let params2: Vec<&str> = params.iter().map(|s| s.as_str()).collect();
+    execute_short_command("ssh", params2, &[])
+}
+
+/// Prints differences for one host and returns how many files differ.
+fn diff_host(
+    config: &Configuration,
+    scope: &Scope,
+    address: &str,
+    files: &[String],
+    generated: &Path,
+    local_config: &str,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let host = RemoteHostCall::new(scope);
+    let export_path = shell_quote(&scope.export_path);
+    let quoted_files: Vec<String> = files.iter().map(|f| shell_quote(f)).collect();
+
+    let checksums_command = format!(
+        "echo {m}sha256; cd {export} 2>/dev/null && sha256sum -- {files} 2>/dev/null; \
+         echo {m}config; {compose} config 2>&1; true",
+        m = SECTION_MARKER,
+        export = export_path,
+        files = quoted_files.join(" "),
+        compose =
+            remote_compose_command(config, scope).args(["--project-name", &project_name(scope)]),
+    );
+    let sections = split_sections(&run_remote(&host, address, &checksums_command)?);
+    let remote_checksums = parse_sha256sum(sections.get("sha256").map_or("", |s| s.as_str()));
+
+    let local_path = Path::new(&config.path);
+    let mut changed = vec![];
+    for file in files {
+        let local_content = fs::read(local_file(config, generated, file)).unwrap_or_default();
+        let local_hash = sha256_hex(&local_content);
+        debug!(
+            "{} local {} remote {:?}",
+            file,
+            local_hash,
+            remote_checksums.get(file)
+        );
+        if remote_checksums.get(file) != Some(&local_hash) {
+            changed.push(file);
+        }
+    }
+
+    let mut report = String::new();
+    for file in &changed {
+        let local_content =
+            fs::read_to_string(local_file(config, generated, file)).unwrap_or_default();
+        let remote_name = format!(
+            "{}:{}",
+            address,
+            Path::new(&scope.export_path).join(file).display()
+        );
+        let remote_content = if remote_checksums.contains_key(*file) {
+            let command = format!("cd {} && cat -- {}", export_path, shell_quote(file));
+            run_remote(&host, address, &command)?
+        } else {
+            report += &format!("{} is missing on host\n", remote_name);
+            String::new()
+        };
+        report += &unified_diff(
+            &local_content,
+            &remote_content,
+            &local_path.join(file).to_string_lossy(),
+            &remote_name,
+        );
+    }
+
+    let absolute_path = fs::canonicalize(local_path)
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| config.path.clone());
+    let remote_config = normalize_remote_config(
+        sections.get("config").map_or("", |s| s.as_str()),
+        &scope.export_path,
+        &absolute_path,
+    );
+    let config_diff = unified_diff(
+        local_config,
+        &remote_config,
+        "local: docker compose config",
+        &format!("{}: docker compose config", address),
+    );
+    report += &config_diff;
+    match output::is_text() {
+        true => print!("{}", report),
+        false => output::emit(Event::Report {
+            kind: "diff",
+            data: json!({"host": address, "files": changed, "diff": report}),
+        }),
+    }
+
+    Ok(changed.len() + usize::from(!config_diff.is_empty()))
+}
+
+/// Hosts which differ from local project and ones which could not be compared.
+struct Drift {
+    hosts: usize,
+    drifted: Vec<String>,
+    failed: Vec<String>,
+}
+
+impl Drift {
+    /// Hosts which could not be compared are always an error.
+    fn check_failed(&mut self) -> Result<(), OpdayError> {
+        if self.failed.is_empty() {
+            return Ok(());
+        }
+        Err(OpdayError::Remote {
+            message: format!(
+                "could not compare {} of {} hosts",
+                self.failed.len(),
+                self.hosts
+            ),
+            hosts: std::mem::take(&mut self.failed),
+        })
+    }
+}
+
+/// Compares local compose files and would-be generated files with deployed ones on hosts.
+/// Generated files are written into temporary directory, so local project stays untouched.
+fn compare(
+    config: &Configuration,
+    target: &RemoteTarget,
+    format: &DockerComposeFormat,
+    build_arg: &[String],
+) -> Result<Drift, Box<dyn std::error::Error>> {
+    let scope = target_scope(config, target)?;
+    let hosts = select_hosts(scope, &target.host)?;
+
+    let build_args = scope.build_args_with(build_arg);
+    let generated = std::env::temp_dir().join(format!("opday-diff-{}", std::process::id()));
+    let written = write_generated_files_into(&generated, scope, format, &build_args);
+
+    let compared = match written {
+        Ok(()) => compare_hosts(config, scope, &hosts, &generated, &build_args),
+        Err(e) => Err(e.into()),
+    };
+    let _ = fs::remove_dir_all(&generated);
+    compared
+}
+
+fn compare_hosts(
+    config: &Configuration,
+    scope: &Scope,
+    hosts: &[&Host],
+    generated: &Path,
+    build_args: &[String],
+) -> Result<Drift, Box<dyn std::error::Error>> {
+    let mut drift = Drift {
+        hosts: hosts.len(),
+        drifted: vec![],
+        failed: vec![],
+    };
+    for host in hosts {
+        let address = &host.address;
+        // hosts can have own compose overrides
+        let host_scope = scope.for_host(host);
+        let files = tracked_files(config, &host_scope);
+        let local_config = local_compose_config(config, &host_scope, generated, build_args)?;
+        match diff_host(
+            config,
+            &host_scope,
+            address,
+            &files,
+            generated,
+            &local_config,
+        ) {
+            Ok(0) => output::host_result(address, "ok", "no drift"),
+            Ok(count) => {
+                drift.drifted.push(address.clone());
+                output::host_result(address, "drift", &format!("{} differences", count));
+            }
+            Err(e) => {
+                drift.failed.push(address.clone());
+                output::host_result(address, "failed", &format!("failed: {}", e));
+            }
+        }
+    }
+    Ok(drift)
+}
+
+/// Fails when deployed files drifted from local project, e.g. for CI checks.
+pub fn diff(
+    config: &Configuration,
+    target: &RemoteTarget,
+    format: &DockerComposeFormat,
+    build_arg: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut drift = compare(config, target, format, build_arg)?;
+    drift.check_failed()?;
+    if !drift.drifted.is_empty() {
+        let message = format!(
+            "drift found on {} of {} hosts",
+            drift.drifted.len(),
+            drift.hosts
+        );
+        return Err(OpdayError::Remote {
+            hosts: drift.drifted,
+            message,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Shows what deploy would change on hosts. Drift is the expected outcome, not an error.
+pub fn plan(
+    config: &Configuration,
+    target: &RemoteTarget,
+    format: &DockerComposeFormat,
+    build_arg: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut drift = compare(config, target, format, build_arg)?;
+    drift.check_failed()?;
+    if drift.drifted.is_empty() {
+        info!("Nothing to deploy, hosts match local project");
+    } else {
+        info!("Deploy would change {}", drift.drifted.join(", "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::read_configuration_raw;
+
+    #[test]
+    fn test_tracked_files() {
+        let config = read_configuration_raw(
+            r#"
+            [environments.prod]
+            hosts = ["root@one"]
+            export_path = "/root/app"
+            registry = "registry"
+            registry_auth_config = "auth"
+            registry_export_auth_config = "export_auth"
+            docker_compose_overrides = ["docker-compose.prod.yaml"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            tracked_files(&config, &config.environments[0]),
+            vec![
+                "docker-compose.yaml",
+                "docker-compose.prod.yaml",
+                ".opday-generated/docker-compose.override-run.yaml",
+                ".opday-generated/run.env",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_local_file() {
+        let mut config = read_configuration_raw("").unwrap();
+        config.path = "/home/me/app".to_owned();
+        let generated = Path::new("/tmp/opday-diff-1");
+        assert_eq!(
+            local_file(&config, generated, "docker-compose.yaml"),
+            Path::new("/home/me/app/docker-compose.yaml")
+        );
+        assert_eq!(
+            local_file(&config, generated, ".opday-generated/run.env"),
+            Path::new("/tmp/opday-diff-1/run.env")
+        );
+    }
+
+    #[test]
+    fn test_project_name() {
+        let mut config = read_configuration_raw(
+            r#"
+            [environments.prod]
+            hosts = ["root@one"]
+            export_path = "/root/My.App"
+            registry = "registry"
+            registry_auth_config = "auth"
+            registry_export_auth_config = "export_auth"
+            docker_compose_overrides = []
+            "#,
+        )
+        .unwrap();
+        config.path = "/home/me/checkout".to_owned();
+        let scope = &config.environments[0];
+        assert_eq!(project_name(scope), "myapp");
+
+        let local = local_compose_args(&config, scope, Path::new("/tmp/opday-diff-1"));
+        assert_eq!(local[..3], ["compose", "--project-name", "myapp"]);
+        let remote = remote_compose_command(&config, scope)
+            .args(["--project-name", &project_name(scope)])
+            .to_string();
+        assert!(remote.ends_with(" --project-name myapp"));
+    }
+
+    #[test]
+    fn test_parse_sha256sum() {
+        let hash = sha256_hex(b"services: {}\n");
+        let output = format!(
+            "{}  docker-compose.yaml\n{} *.opday-generated/run.env\n",
+            hash, hash
+        );
+        let checksums = parse_sha256sum(&output);
+        assert_eq!(checksums.get("docker-compose.yaml"), Some(&hash));
+        assert_eq!(checksums.get(".opday-generated/run.env"), Some(&hash));
+    }
+
+    #[test]
+    fn test_normalize_remote_config() {
+        assert_eq!(
+            normalize_remote_config("context: /root/app/backend\n", "/root/app/", "/home/me/app"),
+            "context: /home/me/app/backend\n"
+        );
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        assert_eq!(unified_diff("a\n", "a\n", "local", "remote"), "");
+        assert_eq!(
+            unified_diff("a\nb\n", "a\nc\n", "local", "remote"),
+            "--- local\n+++ remote\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n"
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use std::path::Path;
+
+use log::warn;
+use sha2::{Digest, Sha256};
+
+use crate::config::{Configuration, Host, Scope};
+use crate::error::OpdayError;
+use crate::exec::{execute_command_lines, execute_short_command, shell_quote, RemoteHostCall};
+use crate::lock;
+use crate::output::{self, Event};
+use crate::release::{Release, RELEASE_FILE};
+use crate::ui;
+
+use super::ops::{select_hosts, target_scope, RemoteTarget};
+use super::{record_release_command, remote_compose_command, GENERATED_DIR};
+
+/// Lock name is the same for the same service and command, so only such jobs exclude each other.
+fn job_lock_name(service: &str, command: &str) -> String {
+    let hash = format!("{:x}", Sha256::digest(command.as_bytes()));
+    format!("job-{}-{}", service, &hash[..12])
+}
+
+fn run_remote(
+    host: &RemoteHostCall,
+    address: &str,
+    command: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let params = host.ssh_args(address, command, false);
+    let params2: Vec<&str> = params.iter().map(|s| s.as_str()).collect();
+    execute_short_command("ssh", params2, &[])
+}
+
+/// Without `--host` job runs on the first host the service is placed on.
+fn job_leader<'a>(scope: &'a Scope, service: &str) -> Result<&'a Host, OpdayError> {
+    scope
+        .hosts
+        .iter()
+        .find(|host| !scope.placed_services(host, &[service]).is_empty())
+        .ok_or_else(|| {
+            OpdayError::Config(format!(
+                "Service `{}` is not placed on any host of `{}`.",
+                service, scope.name
+            ))
+        })
+}
+
+/// Runs `docker compose run --rm` on one host with images and variables of deployed release
+/// and records the job in release history. Without `--host` the leader is chosen by placement.
+pub fn run_job(
+    config: &Configuration,
+    target: &RemoteTarget,
+    service: &str,
+    command: &[String],
+    take_lock: bool,
+    wait: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let scope = target_scope(config, target)?;
+    let leader = match &target.host {
+        Some(_) => select_hosts(scope, &target.host)?[0],
+        None => job_leader(scope, service)?,
+    };
+    let address = &leader.address;
+    let scope = &scope.for_host(leader);
+    let host = RemoteHostCall::new(scope);
+    let job_command = command.join(" ");
+    let description = format!("{} {}", service, job_command);
+
+    let _lock = match take_lock {
+        true => Some(lock::acquire(
+            scope,
+            address,
+            &job_lock_name(service, &job_command),
+            &format!("run-job {}", description),
+            wait,
+        )?),
+        false => None,
+    };
+
+    let release_path = Path::new(&scope.export_path)
+        .join(GENERATED_DIR)
+        .join(RELEASE_FILE);
+    let deployed: Option<Release> = run_remote(
+        &host,
+        address,
+        &format!("cat {}", shell_quote(&release_path.to_string_lossy())),
+    )
+    .ok()
+    .and_then(|output| serde_json::from_str(&output).ok());
+    if let (Some(deployed), true) = (&deployed, output::is_text()) {
+        ui::println(&format!(
+            "Running job on {} with release {}",
+            address, deployed.id
+        ));
+    }
+
+    let remote_command = remote_compose_command(config, scope)
+        .args(["run", "--rm", "-T", service])
+        .args(command)
+        .to_string();
+    let result = {
+        let params = host.ssh_args(address, &remote_command, false);
+        let params2: Vec<&str> = params.iter().map(|s| s.as_str()).collect();
+        execute_command_lines("ssh", params2, &|line| output::line(Some(address), line))
+    };
+
+    let mut job = Release::new(
+        "job",
+        &scope.name,
+        &deployed.map(|d| d.build_args).unwrap_or_default(),
+    );
+    job.command = Some(description.clone());
+    job.status = Some(
+        if result.is_ok() {
+            "succeeded"
+        } else {
+            "failed"
+        }
+        .to_owned(),
+    );
+    // history is secondary, the job result is what user must see
+    match run_remote(&host, address, &record_release_command(scope, &job)) {
+        Ok(_) => output::emit(Event::Release {
+            id: &job.id,
+            kind: &job.kind,
+            environment: &job.environment,
+            host: address,
+        }),
+        Err(e) => warn!("Could not record job in history on {}: {}", address, e),
+    }
+
+    result.map_err(|e| {
+        OpdayError::remote(address, &format!("job `{}` failed: {}", description, e)).into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::read_configuration_raw;
+
+    #[test]
+    fn test_job_lock_name() {
+        let name = job_lock_name("backend", "alembic upgrade head");
+        assert!(name.starts_with("job-backend-"));
+        assert_eq!(name.len(), "job-backend-".len() + 12);
+        assert_eq!(name, job_lock_name("backend", "alembic upgrade head"));
+        assert_ne!(name, job_lock_name("backend", "alembic downgrade -1"));
+    }
+
+    #[test]
+    fn test_job_leader_placement() {
+        let config = read_configuration_raw(
+            r#"
+            [environments.prod]
+            hosts = ["root@db", "root@app"]
+            export_path = "/root/app"
+            registry = "registry"
+            registry_auth_config = "auth"
+            registry_export_auth_config = "export_auth"
+            docker_compose_overrides = []
+            "#,
+        )
+        .unwrap();
+        let mut scope = config.environments[0].clone();
+        assert_eq!(job_leader(&scope, "worker").unwrap().address, "root@db");
+
+        // worker runs only on hosts with `app` role
+        scope.placement = [("app".to_owned(), vec!["worker".to_owned()])].into();
+        scope.hosts[1].roles = vec!["app".to_owned()];
+        assert_eq!(job_leader(&scope, "worker").unwrap().address, "root@app");
+
+        scope.hosts[1].roles = vec![];
+        assert!(matches!(
+            job_leader(&scope, "worker"),
+            Err(OpdayError::Config(_))
+        ));
+    }
+}
@@ -0,0 +1,1362 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use clap::{ArgMatches, Args, Command, FromArgMatches, Subcommand};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use log::info;
+use serde::Deserialize;
+use serde_json::json;
+use serde_yaml::{Mapping, Value};
+
+extern crate term;
+
+use crate::config::{Configuration, DockerComposeFormat, HookStage, Scope};
+use crate::error::OpdayError;
+use crate::exec::{
+    env_file_content, execute_command, execute_command_with, shell_quote, ExecOptions,
+    RemoteCommand, RemoteHostCall, RetryPolicy,
+};
+use crate::hooks::{run_failure_hooks, run_hooks};
+use crate::lock::{self, DEPLOY_LOCK};
+use crate::output::{self, Event};
+use crate::pipeline::{run_pipeline, Pipeline};
+use crate::provider::Provider;
+use crate::release::{Release, HISTORY_FILE, RELEASE_FILE, STATE_DIR};
+use crate::ui;
+
+mod diff;
+mod job;
+mod ops;
+mod status;
+mod sync;
+
+pub use ops::RemoteTarget;
+pub use sync::SyncOptions;
+
+use status::{expected_images, local_digests};
+
+/// Arguments of `docker` subcommand.
+#[derive(Args)]
+pub struct DockerArgs {
+    /// Subcommand
+    #[command(subcommand)]
+    pub command: DockerProviderCommands,
+
+    /// Names
+    #[arg(value_name = "NAME")]
+    pub names: Vec<String>,
+
+    /// Sets a custom config file
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Build args
+    #[arg(short, long, value_name = "build-arg")]
+    pub build_arg: Vec<String>,
+}
+
+#[derive(Subcommand)]
+pub enum DockerProviderCommands {
+    /// Login
+    Login {
+        /// Path to existing docker-config.json file
+        #[arg(short = 'f', long = "file", value_name = "FILE")]
+        docker_json_file: Option<PathBuf>,
+
+        #[arg(short = 'u', long = "username", value_name = "USERNAME")]
+        username: Option<String>,
+
+        #[arg(short = 'p', long = "password", value_name = "PASSWORD")]
+        password: Option<String>,
+
+        #[arg(long = "password-stdin", value_name = "PASSWORD-STDIN", action)]
+        password_stdin: bool,
+
+        /// Path to config file
+        #[arg(short, long, value_name = "FILE")]
+        config: Option<PathBuf>,
+
+        /// Environment name
+        #[arg(short = 'e', long = "env", value_name = "NAME", env = "OPDAY_ENV")]
+        environment: Option<String>,
+    },
+    /// Build images
+    Build {
+        /// Names
+        #[arg(value_name = "NAME")]
+        names: Vec<String>,
+
+        // Path to config file
+        #[arg(short, long, value_name = "FILE")]
+        config: Option<PathBuf>,
+
+        /// Environment name
+        #[arg(short = 'e', long = "env", value_name = "NAME", env = "OPDAY_ENV")]
+        environment: Option<String>,
+
+        /// Build args
+        #[arg(short, long, value_name = "build-arg")]
+        build_arg: Vec<String>,
+    },
+    /// Pushes images
+    Push {
+        /// Names
+        #[arg(value_name = "NAME")]
+        names: Vec<String>,
+
+        /// Path to config file
+        #[arg(short, long, value_name = "FILE")]
+        config: Option<PathBuf>,
+
+        /// Environment name
+        #[arg(short = 'e', long = "env", value_name = "NAME", env = "OPDAY_ENV")]
+        environment: Option<String>,
+
+        /// Build args
+        #[arg(short, long, value_name = "build-arg")]
+        build_arg: Vec<String>,
+    },
+    /// Deploys images
+    Deploy {
+        /// Names
+        #[arg(value_name = "NAME")]
+        names: Vec<String>,
+
+        /// Path to config file
+        #[arg(short, long, value_name = "FILE")]
+        config: Option<PathBuf>,
+
+        /// Environment name
+        #[arg(short = 'e', long = "env", value_name = "NAME", env = "OPDAY_ENV")]
+        environment: Option<String>,
+
+        /// Build args
+        #[arg(short, long, value_name = "build-arg")]
+        build_arg: Vec<String>,
+
+        #[command(flatten)]
+        sync: SyncOptions,
+
+        /// Wait for lock held by another deploy instead of failing
+        #[arg(long, action)]
+        wait: bool,
+    },
+    /// Builds and pushes images
+    BuildPush {
+        /// Names
+        #[arg(value_name = "NAME")]
+        names: Vec<String>,
+
+        /// Path to config file
+        #[arg(short, long, value_name = "FILE")]
+        config: Option<PathBuf>,
+
+        /// Environment name
+        #[arg(short = 'e', long = "env", value_name = "NAME", env = "OPDAY_ENV")]
+        environment: Option<String>,
+
+        /// Build args
+        #[arg(short, long, value_name = "build-arg")]
+        build_arg: Vec<String>,
+
+        /// Resume from step after failure
+        #[arg(long, value_name = "STEP")]
+        from: Option<String>,
+    },
+    /// Builds, pushes and deploys images
+    BuildPushDeploy {
+        /// Names
+        #[arg(value_name = "NAME")]
+        names: Vec<String>,
+
+        /// Path to config file
+        #[arg(short, long, value_name = "FILE")]
+        config: Option<PathBuf>,
+
+        /// Environment name
+        #[arg(short = 'e', long = "env", value_name = "NAME", env = "OPDAY_ENV")]
+        environment: Option<String>,
+
+        /// Build args
+        #[arg(short, long, value_name = "build-arg")]
+        build_arg: Vec<String>,
+
+        #[command(flatten)]
+        sync: SyncOptions,
+
+        /// Wait for lock held by another deploy instead of failing
+        #[arg(long, action)]
+        wait: bool,
+
+        /// Resume from step after failure
+        #[arg(long, value_name = "STEP")]
+        from: Option<String>,
+    },
+    /// Shows differences between local project and deployed files on hosts
+    Diff {
+        #[command(flatten)]
+        target: RemoteTarget,
+
+        /// Build args
+        #[arg(short, long, value_name = "build-arg")]
+        build_arg: Vec<String>,
+    },
+    /// Lists containers on hosts
+    Ps {
+        /// Services
+        #[arg(value_name = "SERVICE")]
+        services: Vec<String>,
+
+        #[command(flatten)]
+        target: RemoteTarget,
+    },
+    /// Shows logs of services from hosts
+    Logs {
+        /// Services
+        #[arg(value_name = "SERVICE")]
+        services: Vec<String>,
+
+        /// Follow log output
+        #[arg(short, long, action)]
+        follow: bool,
+
+        /// Show logs since timestamp or relative time, e.g. `10m`
+        #[arg(long, value_name = "SINCE")]
+        since: Option<String>,
+
+        #[command(flatten)]
+        target: RemoteTarget,
+    },
+    /// Restarts services on hosts
+    Restart {
+        /// Services
+        #[arg(value_name = "SERVICE")]
+        services: Vec<String>,
+
+        #[command(flatten)]
+        target: RemoteTarget,
+    },
+    /// Stops services on hosts
+    Stop {
+        /// Services
+        #[arg(value_name = "SERVICE")]
+        services: Vec<String>,
+
+        #[command(flatten)]
+        target: RemoteTarget,
+    },
+    /// Stops and removes containers and networks on hosts
+    Down {
+        #[command(flatten)]
+        target: RemoteTarget,
+    },
+    /// Executes command in running service container
+    Exec {
+        #[command(flatten)]
+        target: RemoteTarget,
+
+        /// Service
+        #[arg(value_name = "SERVICE")]
+        service: String,
+
+        /// Command with arguments
+        #[arg(
+            value_name = "COMMAND",
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            required = true
+        )]
+        command: Vec<String>,
+    },
+    /// Runs one-off command in a new service container
+    Run {
+        #[command(flatten)]
+        target: RemoteTarget,
+
+        /// Service
+        #[arg(value_name = "SERVICE")]
+        service: String,
+
+        /// Command with arguments
+        #[arg(
+            value_name = "COMMAND",
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            required = true
+        )]
+        command: Vec<String>,
+    },
+    /// Runs one-off job with deployed release on one host and records it in history
+    RunJob {
+        #[command(flatten)]
+        target: RemoteTarget,
+
+        /// Run even if the same job is already running
+        #[arg(long, action)]
+        no_lock: bool,
+
+        /// Wait for the same job to finish instead of failing
+        #[arg(long, action)]
+        wait: bool,
+
+        /// Service
+        #[arg(value_name = "SERVICE")]
+        service: String,
+
+        /// Command with arguments
+        #[arg(
+            value_name = "COMMAND",
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            required = true
+        )]
+        command: Vec<String>,
+    },
+}
+
+/// Registry credentials copied to hosts on login.
+#[derive(Clone, Debug)]
+pub enum LoginCredentials {
+    /// Existing docker config file with `auths`
+    DockerConfig(PathBuf),
+    /// Username and password written into generated docker config
+    Password { username: String, password: String },
+}
+
+#[derive(Clone, Debug)]
+pub struct LoginOptions {
+    pub environment: Option<String>,
+    pub credentials: LoginCredentials,
+}
+
+/// Images and environment which `build`, `push` and `deploy` work with.
+#[derive(Clone, Debug, Default)]
+pub struct BuildOptions {
+    pub environment: Option<String>,
+    pub names: Vec<String>,
+    pub build_args: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DeployOptions {
+    pub build: BuildOptions,
+    pub sync: SyncOptions,
+    /// Wait for lock held by another deploy instead of failing
+    pub wait: bool,
+}
+
+/// Credentials from login flags, the password is read from stdin with `password_stdin`.
+fn login_credentials(
+    docker_json_file: &Option<PathBuf>,
+    username: &Option<String>,
+    password: &Option<String>,
+    password_stdin: bool,
+) -> Result<LoginCredentials, Box<dyn std::error::Error>> {
+    let input_error = |message: &str| Err(OpdayError::Input(message.to_owned()).into());
+    match (docker_json_file, username) {
+        (Some(_), Some(_)) => input_error("Username is conflicting with -f option."),
+        (Some(_), None) if password.is_some() || password_stdin => {
+            input_error("Password is conflicting with -f option.")
+        }
+        (Some(docker_json_file), None) => {
+            Ok(LoginCredentials::DockerConfig(docker_json_file.clone()))
+        }
+        (None, None) => input_error("Username is required for login."),
+        (None, Some(_)) if password.is_some() == password_stdin => {
+            input_error("Password is required from `password` or `password-stdin` options.")
+        }
+        (None, Some(username)) => {
+            let password = match password {
+                Some(password) => password.clone(),
+                // read password interactively
+                None => {
+                    let mut input = String::new();
+                    io::stdin().read_to_string(&mut input)?;
+                    input.trim().to_owned()
+                }
+            };
+            Ok(LoginCredentials::Password {
+                username: username.clone(),
+                password,
+            })
+        }
+    }
+}
+
+fn login(config: &Configuration, options: &LoginOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let environment = options.environment.as_deref();
+    let docker_json_file = match &options.credentials {
+        LoginCredentials::DockerConfig(docker_json_file) => docker_json_file.clone(),
+        LoginCredentials::Password { username, password } => {
+            let scope = config.required_environment(environment)?;
+            write_docker_json(config, &scope.registry, username, password)?
+        }
+    };
+
+    let scope = config.required_environment(environment)?;
+    for host in &scope.hosts {
+        let address = &host.address;
+        let host_scope = scope.for_host(host);
+        let remote = RemoteHostCall::new(&host_scope);
+        let exec_options = ExecOptions::new(&host_scope);
+        let retry = RetryPolicy::new(&host_scope);
+
+        info!("Logging in to {} on {}", scope.registry, address);
+        // scp docker registry auth
+        {
+            let params = remote.scp_args(
+                &docker_json_file.to_string_lossy(),
+                address,
+                &scope.registry_export_auth_config,
+            );
+            let params2: Vec<&str> = params.iter().map(|s| s.as_str()).collect();
+            retry.run(|| execute_command_with("scp", params2.clone(), &[], &exec_options))?;
+        }
+
+        // docker login for registry
+        {
+            let login_command = RemoteCommand::new("docker")
+                .args(["login", &scope.registry])
+                .to_string();
+            let params = remote.ssh_args(address, &login_command, false);
+            let params2: Vec<&str> = params.iter().map(|s| s.as_str()).collect();
+            retry.run(|| execute_command_with("ssh", params2.clone(), &[], &exec_options))?;
+        }
+    }
+
+    // TODO: remove secret file after login
+
+    Ok(())
+}
+
+/// Writes docker config with registry credentials into `.opday-generated` and returns its path.
+fn write_docker_json(
+    config: &Configuration,
+    registry: &str,
+    username: &str,
+    password: &str,
+) -> Result<PathBuf, OpdayError> {
+    let internal_files = Path::new(&config.path).join(GENERATED_DIR);
+    let docker_json_file_path = internal_files.join(DOCKER_JSON_FILE);
+
+    let base_64_username_and_password =
+        STANDARD.encode(format!("{}:{}", username, password).as_bytes());
+    let docker_json_value = json!({
+        "auths": {
+            registry: {
+                "auth": base_64_username_and_password
+            }
+        },
+    });
+
+    fs::create_dir_all(&internal_files).map_err(|e| OpdayError::io(&internal_files, e))?;
+    fs::write(&docker_json_file_path, docker_json_value.to_string())
+        .map_err(|e| OpdayError::io(&docker_json_file_path, e))?;
+    Ok(docker_json_file_path)
+}
+
+fn build(
+    config: &Configuration,
+    _format: &DockerComposeFormat,
+    options: &BuildOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let scope = config
+        .environment(options.environment.as_deref())
+        .map_err(OpdayError::Config)?;
+
+    // Bake docker compose string
+    let mut build_command_args: Vec<String> = Vec::new();
+    build_command_args.push("compose".to_owned());
+
+    // Add user main docker compose file
+    build_command_args.push("-f".to_owned());
+    let docker_compose_path = Path::new(&config.path).join(&config.docker_compose_file);
+    build_command_args.push(docker_compose_path.to_string_lossy().into_owned());
+
+    // Add user override files
+    let mut build_arg = options.build_args.clone();
+    if let Some(scope) = scope {
+        for override_file in &scope.docker_compose_overrides {
+            build_command_args.push("-f".to_owned());
+            let override_file_path = Path::new(&config.path).join(override_file);
+            build_command_args.push(override_file_path.to_string_lossy().into_owned());
+        }
+        build_arg = scope.build_args_with(&build_arg);
+        run_hooks(config, scope, HookStage::PreBuild, &build_arg, &[])?;
+    }
+
+    build_command_args.push("build".to_owned());
+    let build_command_args2: Vec<&str> = build_command_args.iter().map(|s| s.as_str()).collect();
+
+    ui::task("Building images", || {
+        execute_command("docker", build_command_args2, &build_arg)
+    })?;
+    if let Some(scope) = scope {
+        run_hooks(config, scope, HookStage::PostBuild, &build_arg, &[])?;
+    }
+    Ok(())
+}
+
+fn push(
+    config: &Configuration,
+    _format: &DockerComposeFormat,
+    options: &BuildOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let scope = config.required_environment(options.environment.as_deref())?;
+
+    let mut build_command_args: Vec<String> = Vec::new();
+    build_command_args.push("compose".to_owned());
+
+    build_command_args.push("-f".to_owned());
+    let docker_compose_path = Path::new(&config.path).join(&config.docker_compose_file);
+    build_command_args.push(docker_compose_path.to_string_lossy().into_owned());
+
+    for override_file in &scope.docker_compose_overrides {
+        build_command_args.push("-f".to_owned());
+        let docker_compose_override_path = Path::new(&config.path).join(override_file);
+        build_command_args.push(docker_compose_override_path.to_string_lossy().into_owned());
+    }
+    build_command_args.push("push".to_owned());
+    let build_command_args2: Vec<&str> = build_command_args.iter().map(|s| s.as_str()).collect();
+
+    let build_args = scope.build_args_with(&options.build_args);
+    ui::task(&format!("Pushing images to {}", scope.registry), || {
+        execute_command("docker", build_command_args2, &build_args)
+    })?;
+
+    if !output::is_text() {
+        for (service, image) in expected_images(config, scope, &build_args) {
+            let repository = image.rsplit_once(':').map_or(image.as_str(), |(r, _)| r);
+            let digest = local_digests(&image)
+                .into_iter()
+                .find(|digest| digest.starts_with(&format!("{}@", repository)));
+            output::emit(Event::Image {
+                service: &service,
+                image: &image,
+                digest: digest.as_deref(),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn deploy(
+    config: &Configuration,
+    format: &DockerComposeFormat,
+    options: &DeployOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let scope = config.required_environment(options.build.environment.as_deref())?;
+
+    let build_args = scope.build_args_with(&options.build.build_args);
+    let internal_files = write_generated_files(config, scope, format, &build_args)?;
+    let release = Release::new("deploy", &scope.name, &build_args);
+    let release_path = internal_files.join(RELEASE_FILE);
+    fs::write(&release_path, serde_json::to_string_pretty(&release)?)
+        .map_err(|e| OpdayError::io(&release_path, e))?;
+
+    let services = compose_services(format);
+    scope.check_placement(&services)?;
+
+    let _locks = lock::acquire_all(
+        scope,
+        &scope.hosts,
+        DEPLOY_LOCK,
+        &format!("deploy {}", build_args.join(" ")),
+        options.wait,
+    )?;
+
+    info!(
+        "Deploying release {} of `{}` to {}",
+        release.id,
+        scope.name,
+        scope.addresses().join(", ")
+    );
+    let targets: Vec<(Scope, Vec<&str>)> = scope
+        .hosts
+        .iter()
+        .map(|host| (scope.for_host(host), scope.placed_services(host, &services)))
+        .collect();
+
+    for (host_scope, placed) in &targets {
+        let address = &host_scope.hosts[0].address;
+        let host = RemoteHostCall::new(host_scope);
+        let exec_options = ExecOptions::new(host_scope);
+        let retry = RetryPolicy::new(host_scope);
+        let stats = ui::task(&format!("Syncing files to {}", address), || {
+            retry.run(|| sync::sync(config, host_scope, address, &options.sync))
+        })?;
+        output::host_result(
+            address,
+            "synced",
+            &format!(
+                "synced {} files ({} bytes, {} bytes sent)",
+                stats.files_transferred, stats.transferred_size, stats.bytes_sent
+            ),
+        );
+
+        // new images are pulled before pre_deploy hooks, so migrations run with them
+        let pull_command = remote_compose_command(config, host_scope)
+            .args(["pull", "--ignore-pull-failures"])
+            .args(placed_services_args(host_scope, placed))
+            .to_string();
+        ui::task(&format!("Pulling images on {}", address), || {
+            let params = host.ssh_args(address, &pull_command, false);
+            let params2: Vec<&str> = params.iter().map(|s| s.as_str()).collect();
+            retry.run(|| execute_command_with("ssh", params2.clone(), &[], &exec_options))
+        })?;
+    }
+
+    run_hooks(config, scope, HookStage::PreDeploy, &build_args, &[])?;
+
+    for (host_scope, placed) in &targets {
+        let address = &host_scope.hosts[0].address;
+        let host = RemoteHostCall::new(host_scope);
+        let exec_options = ExecOptions::new(host_scope);
+        if placed.is_empty() {
+            info!("No services placed on {}", address);
+        } else {
+            // services placed on other hosts are not started here as dependencies
+            let no_deps = match host_scope.placement.is_empty() {
+                true => None,
+                false => Some("--no-deps"),
+            };
+            let deploy_command = remote_compose_command(config, host_scope)
+                .args(["up", "-d", "--build"])
+                .args(no_deps)
+                .args(placed_services_args(host_scope, placed))
+                .to_string();
+            ui::task(&format!("Starting services on {}", address), || {
+                let params = host.ssh_args(address, &deploy_command, false);
+                let params2: Vec<&str> = params.iter().map(|s| s.as_str()).collect();
+                execute_command_with("ssh", params2, &[], &exec_options)
+            })?;
+        }
+
+        let history_command = record_release_command(host_scope, &release);
+        let params = host.ssh_args(address, &history_command, false);
+        let params2: Vec<&str> = params.iter().map(|s| s.as_str()).collect();
+        execute_command_with("ssh", params2, &[], &exec_options)?;
+        output::emit(Event::Release {
+            id: &release.id,
+            kind: &release.kind,
+            environment: &release.environment,
+            host: address,
+        });
+    }
+
+    run_hooks(config, scope, HookStage::PostDeploy, &build_args, &[])?;
+
+    Ok(())
+}
+
+/// Service names of compose file.
+pub(crate) fn compose_services(format: &DockerComposeFormat) -> Vec<&str> {
+    format
+        .services
+        .keys()
+        .filter_map(|service| service.as_str())
+        .collect()
+}
+
+/// Services to pass to `docker compose` on host, none means all of them.
+fn placed_services_args<'a>(scope: &Scope, placed: &'a [&'a str]) -> &'a [&'a str] {
+    match scope.placement.is_empty() {
+        true => &[],
+        false => placed,
+    }
+}
+
+/// Writes files generated for deploy into `.opday-generated` and returns its path.
+pub(crate) fn write_generated_files(
+    config: &Configuration,
+    scope: &Scope,
+    format: &DockerComposeFormat,
+    build_args: &[String],
+) -> Result<PathBuf, OpdayError> {
+    let internal_files = Path::new(&config.path).join(GENERATED_DIR);
+    write_generated_files_into(&internal_files, scope, format, build_args)?;
+    Ok(internal_files)
+}
+
+/// Writes files generated for deploy into `internal_files` directory.
+pub(crate) fn write_generated_files_into(
+    internal_files: &Path,
+    scope: &Scope,
+    format: &DockerComposeFormat,
+    build_args: &[String],
+) -> Result<(), OpdayError> {
+    fs::create_dir_all(internal_files).map_err(|e| OpdayError::io(internal_files, e))?;
+
+    let write = |name: &str, content: &str| {
+        let path = internal_files.join(name);
+        fs::write(&path, content).map_err(|e| OpdayError::io(&path, e))
+    };
+    write(".gitignore", "*\n")?;
+
+    // variables for compose files interpolation, reused by later commands on host
+    write(GENERATED_ENV_FILE, &env_file_content(build_args)?)?;
+
+    let all_services = compose_services(format);
+    write(GENERATED_RUN_FILE, &make_run_file(format, &all_services))?;
+    if !scope.placement.is_empty() {
+        for host in &scope.hosts {
+            write(
+                &generated_run_file(&scope.for_host(host)),
+                &make_run_file(format, &scope.placed_services(host, &all_services)),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Run override for `services` of compose file.
+fn make_run_file(format: &DockerComposeFormat, services: &[&str]) -> String {
+    let mut run_format = DockerComposeFormat {
+        version: format.version.clone(),
+        services: Mapping::new(),
+    };
+
+    for service in services {
+        // override environment
+        let mut run_service_map = Mapping::new();
+        // TODO: Add only when we have build
+        // run_service_map.insert(Value::String((&"build").to_string()), "!reset null".into());
+        run_service_map.insert(
+            Value::String((&"environment").to_string()),
+            Value::Mapping(Mapping::new()),
+        );
+
+        run_format.services.insert(
+            Value::String((*service).to_owned()),
+            Value::Mapping(run_service_map),
+        );
+    }
+    serde_yaml::to_string(&run_format).expect("Could not serialize run override.")
+}
+
+/// Run override used on the host of `scope`, own one per host when services are placed by roles.
+pub(crate) fn generated_run_file(scope: &Scope) -> String {
+    match (scope.placement.is_empty(), scope.hosts.as_slice()) {
+        (false, [host]) => {
+            let host_name: String = host
+                .address
+                .chars()
+                .map(|c| match c.is_ascii_alphanumeric() || ".-_".contains(c) {
+                    true => c,
+                    false => '_',
+                })
+                .collect();
+            format!("docker-compose.override-run.{}.yaml", host_name)
+        }
+        _ => GENERATED_RUN_FILE.to_owned(),
+    }
+}
+
+/// Remote command appending release to history under `export_path`.
+pub(crate) fn record_release_command(scope: &Scope, release: &Release) -> String {
+    let state_dir = Path::new(&scope.export_path).join(STATE_DIR);
+    format!(
+        "mkdir -p {} && printf '%s\\n' {} >> {}",
+        shell_quote(&state_dir.to_string_lossy()),
+        shell_quote(&serde_json::to_string(release).expect("Could not serialize release.")),
+        shell_quote(&state_dir.join(HISTORY_FILE).to_string_lossy()),
+    )
+}
+
+pub(crate) const GENERATED_DIR: &str = ".opday-generated";
+pub(crate) const GENERATED_RUN_FILE: &str = "docker-compose.override-run.yaml";
+pub(crate) const GENERATED_ENV_FILE: &str = "run.env";
+/// Registry credentials written by login, never synced to hosts on deploy.
+pub(crate) const DOCKER_JSON_FILE: &str = "docker.json";
+
+/// `docker compose` command with the same files and variables on remote host as deploy uses.
+pub(crate) fn remote_compose_command(config: &Configuration, scope: &Scope) -> RemoteCommand {
+    let export_path = Path::new(&scope.export_path);
+    let internal_files_export = export_path.join(GENERATED_DIR);
+
+    let mut command = RemoteCommand::new("docker").args([
+        "compose",
+        "--env-file",
+        &internal_files_export
+            .join(GENERATED_ENV_FILE)
+            .to_string_lossy(),
+        "-f",
+        &export_path
+            .join(&config.docker_compose_file)
+            .to_string_lossy(),
+    ]);
+    for override_file in &scope.docker_compose_overrides {
+        command = command.args(["-f", &export_path.join(override_file).to_string_lossy()]);
+    }
+    command.args([
+        "-f",
+        &internal_files_export
+            .join(generated_run_file(scope))
+            .to_string_lossy(),
+    ])
+}
+
+pub fn prepare_config(command: &DockerProviderCommands) -> Option<PathBuf> {
+    match &command {
+        DockerProviderCommands::Build { config, .. } => config.clone(),
+        DockerProviderCommands::Push { config, .. } => config.clone(),
+        DockerProviderCommands::Deploy { config, .. } => config.clone(),
+        DockerProviderCommands::BuildPush { config, .. } => config.clone(),
+        DockerProviderCommands::BuildPushDeploy { config, .. } => config.clone(),
+        DockerProviderCommands::Login { config, .. } => config.clone(),
+        DockerProviderCommands::Diff { target, .. }
+        | DockerProviderCommands::Ps { target, .. }
+        | DockerProviderCommands::Logs { target, .. }
+        | DockerProviderCommands::Restart { target, .. }
+        | DockerProviderCommands::Stop { target, .. }
+        | DockerProviderCommands::Down { target }
+        | DockerProviderCommands::Exec { target, .. }
+        | DockerProviderCommands::Run { target, .. }
+        | DockerProviderCommands::RunJob { target, .. } => target.config.clone(),
+    }
+}
+
+pub fn prepare_environment(command: &DockerProviderCommands) -> Option<&str> {
+    match &command {
+        DockerProviderCommands::Build { environment, .. } => environment.as_deref(),
+        DockerProviderCommands::Push { environment, .. } => environment.as_deref(),
+        DockerProviderCommands::Deploy { environment, .. } => environment.as_deref(),
+        DockerProviderCommands::BuildPush { environment, .. } => environment.as_deref(),
+        DockerProviderCommands::BuildPushDeploy { environment, .. } => environment.as_deref(),
+        DockerProviderCommands::Login { environment, .. } => environment.as_deref(),
+        DockerProviderCommands::Diff { target, .. }
+        | DockerProviderCommands::Ps { target, .. }
+        | DockerProviderCommands::Logs { target, .. }
+        | DockerProviderCommands::Restart { target, .. }
+        | DockerProviderCommands::Stop { target, .. }
+        | DockerProviderCommands::Down { target }
+        | DockerProviderCommands::Exec { target, .. }
+        | DockerProviderCommands::Run { target, .. }
+        | DockerProviderCommands::RunJob { target, .. } => target.environment.as_deref(),
+    }
+}
+
+/// Docker compose project of `config` which images are built, pushed and deployed to hosts.
+pub struct DockerProvider<'a> {
+    config: &'a Configuration,
+    format: DockerComposeFormat,
+}
+
+impl<'a> DockerProvider<'a> {
+    /// Reads compose file of `config`.
+    pub fn new(config: &'a Configuration) -> Result<DockerProvider<'a>, OpdayError> {
+        Ok(DockerProvider {
+            config,
+            format: read_compose_file(config)?,
+        })
+    }
+
+    /// Copies registry credentials to hosts and logs in there.
+    pub fn login(&self, options: &LoginOptions) -> Result<(), Box<dyn std::error::Error>> {
+        login(self.config, options)
+    }
+
+    pub fn build(&self, options: &BuildOptions) -> Result<(), Box<dyn std::error::Error>> {
+        self.with_failure_hooks(options, || build(self.config, &self.format, options))
+    }
+
+    pub fn push(&self, options: &BuildOptions) -> Result<(), Box<dyn std::error::Error>> {
+        self.with_failure_hooks(options, || push(self.config, &self.format, options))
+    }
+
+    pub fn deploy(&self, options: &DeployOptions) -> Result<(), Box<dyn std::error::Error>> {
+        self.with_failure_hooks(&options.build, || {
+            deploy(self.config, &self.format, options)
+        })
+    }
+
+    /// Builds and pushes images, `from` skips steps before it.
+    pub fn build_push(
+        &self,
+        options: &BuildOptions,
+        from: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.with_failure_hooks(options, || {
+            let pipeline = Pipeline::new()
+                .step("build", || build(self.config, &self.format, options))
+                .step("push", || push(self.config, &self.format, options));
+            run_pipeline(pipeline, from)
+        })
+    }
+
+    /// Builds, pushes and deploys images, `from` skips steps before it.
+    pub fn build_push_deploy(
+        &self,
+        options: &DeployOptions,
+        from: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.with_failure_hooks(&options.build, || {
+            run_pipeline(self.build_push_deploy_pipeline(options), from)
+        })
+    }
+
+    fn build_push_deploy_pipeline<'b>(&'b self, options: &'b DeployOptions) -> Pipeline<'b> {
+        Pipeline::new()
+            .step("build", || build(self.config, &self.format, &options.build))
+            .step("push", || push(self.config, &self.format, &options.build))
+            .step("deploy", || deploy(self.config, &self.format, options))
+    }
+
+    /// Differences between local project and deployed files on hosts.
+    pub fn diff(
+        &self,
+        target: &RemoteTarget,
+        build_args: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        diff::diff(self.config, target, &self.format, build_args)
+    }
+
+    /// What deploy would change on hosts, local project is left untouched.
+    pub fn plan(
+        &self,
+        target: &RemoteTarget,
+        build_args: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        diff::plan(self.config, target, &self.format, build_args)
+    }
+
+    /// Runs `on_failure` hooks of environment when `run` fails and passes the error on.
+    fn with_failure_hooks(
+        &self,
+        options: &BuildOptions,
+        run: impl FnOnce() -> Result<(), Box<dyn std::error::Error>>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let result = run();
+        if let (Err(e), Ok(Some(scope))) = (
+            &result,
+            self.config.environment(options.environment.as_deref()),
+        ) {
+            run_failure_hooks(
+                self.config,
+                scope,
+                &scope.build_args_with(&options.build_args),
+                &e.to_string(),
+            );
+        }
+        result
+    }
+}
+
+/// Docker provider in registry, deploys are driven by [`DockerProvider`].
+pub struct Docker;
+
+/// `[providers.docker]` section, docker itself is configured by `environments`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DockerSection {}
+
+impl Provider for Docker {
+    fn name(&self) -> &'static str {
+        "docker"
+    }
+
+    fn command(&self) -> Command {
+        // doc comment of `DockerArgs` would become the about otherwise
+        DockerArgs::augment_args(Command::new(self.name())).about("Docker provider")
+    }
+
+    fn config_file(&self, matches: &ArgMatches) -> Option<PathBuf> {
+        let args = DockerArgs::from_arg_matches(matches).ok()?;
+        prepare_config(&args.command).or(args.config)
+    }
+
+    fn check_config(&self, section: &toml::Value) -> Result<(), String> {
+        section
+            .clone()
+            .try_into::<DockerSection>()
+            .map(|_| ())
+            .map_err(|e| e.message().to_owned())
+    }
+
+    fn run(
+        &self,
+        config: &Configuration,
+        matches: &ArgMatches,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let args = DockerArgs::from_arg_matches(matches)?;
+        docker_entrypoint(&args.command, config)
+    }
+
+    fn plan(
+        &self,
+        config: &Configuration,
+        environment: Option<&str>,
+        build_args: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let target = RemoteTarget {
+            config: None,
+            environment: environment.map(|e| e.to_owned()),
+            host: None,
+        };
+        DockerProvider::new(config)?.plan(&target, build_args)
+    }
+
+    fn apply(
+        &self,
+        config: &Configuration,
+        environment: Option<&str>,
+        build_args: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let options = DeployOptions {
+            build: BuildOptions {
+                environment: environment.map(|e| e.to_owned()),
+                build_args: build_args.to_vec(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        DockerProvider::new(config)?.build_push_deploy(&options, None)
+    }
+
+    fn status(
+        &self,
+        config: &Configuration,
+        environment: Option<&str>,
+        build_args: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        status::status(config, environment, build_args)
+    }
+}
+
+/// Reads services of compose file under `path`.
+pub(crate) fn read_compose_file(config: &Configuration) -> Result<DockerComposeFormat, OpdayError> {
+    let path = Path::new(&config.path).join(&config.docker_compose_file);
+    let compose_error = |message: String| OpdayError::Compose {
+        path: path.clone(),
+        message,
+    };
+    let content = fs::read_to_string(&path).map_err(|e| compose_error(e.to_string()))?;
+    serde_yaml::from_str(&content).map_err(|e| compose_error(e.to_string()))
+}
+
+/// Runs docker subcommand of CLI.
+pub fn docker_entrypoint(
+    command: &DockerProviderCommands,
+    global_config: &Configuration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let environment = prepare_environment(command).map(|e| e.to_owned());
+    let build_options = |names: &[String], build_arg: &[String]| BuildOptions {
+        environment: environment.clone(),
+        names: names.to_vec(),
+        build_args: build_arg.to_vec(),
+    };
+    match &command {
+        DockerProviderCommands::Login {
+            docker_json_file,
+            username,
+            password,
+            password_stdin,
+            ..
+        } => login(
+            global_config,
+            &LoginOptions {
+                environment: environment.clone(),
+                credentials: login_credentials(
+                    docker_json_file,
+                    username,
+                    password,
+                    *password_stdin,
+                )?,
+            },
+        ),
+        DockerProviderCommands::Build {
+            names, build_arg, ..
+        } => DockerProvider::new(global_config)?.build(&build_options(names, build_arg)),
+        DockerProviderCommands::Push {
+            names, build_arg, ..
+        } => DockerProvider::new(global_config)?.push(&build_options(names, build_arg)),
+        DockerProviderCommands::Deploy {
+            names,
+            build_arg,
+            sync,
+            wait,
+            ..
+        } => DockerProvider::new(global_config)?.deploy(&DeployOptions {
+            build: build_options(names, build_arg),
+            sync: sync.clone(),
+            wait: *wait,
+        }),
+        DockerProviderCommands::BuildPush {
+            names,
+            build_arg,
+            from,
+            ..
+        } => DockerProvider::new(global_config)?
+            .build_push(&build_options(names, build_arg), from.as_deref()),
+        DockerProviderCommands::BuildPushDeploy {
+            names,
+            build_arg,
+            sync,
+            wait,
+            from,
+            ..
+        } => DockerProvider::new(global_config)?.build_push_deploy(
+            &DeployOptions {
+                build: build_options(names, build_arg),
+                sync: sync.clone(),
+                wait: *wait,
+            },
+            from.as_deref(),
+        ),
+        DockerProviderCommands::Diff { target, build_arg } => {
+            DockerProvider::new(global_config)?.diff(target, build_arg)
+        }
+        DockerProviderCommands::Ps { services, target } => {
+            let mut args = vec!["ps"];
+            args.extend(services.iter().map(|s| s.as_str()));
+            ops::compose(global_config, target, &args)
+        }
+        DockerProviderCommands::Logs {
+            services,
+            follow,
+            since,
+            target,
+        } => {
+            let mut args = vec!["logs"];
+            if *follow {
+                args.push("--follow");
+            }
+            if let Some(since) = since {
+                args.push("--since");
+                args.push(since);
+            }
+            args.extend(services.iter().map(|s| s.as_str()));
+            ops::compose(global_config, target, &args)
+        }
+        DockerProviderCommands::Restart { services, target } => {
+            let mut args = vec!["restart"];
+            args.extend(services.iter().map(|s| s.as_str()));
+            ops::compose(global_config, target, &args)
+        }
+        DockerProviderCommands::Stop { services, target } => {
+            let mut args = vec!["stop"];
+            args.extend(services.iter().map(|s| s.as_str()));
+            ops::compose(global_config, target, &args)
+        }
+        DockerProviderCommands::Down { target } => ops::compose(global_config, target, &["down"]),
+        DockerProviderCommands::Exec {
+            target,
+            service,
+            command,
+        } => ops::compose_exec(global_config, target, "exec", service, command),
+        DockerProviderCommands::Run {
+            target,
+            service,
+            command,
+        } => ops::compose_exec(global_config, target, "run", service, command),
+        DockerProviderCommands::RunJob {
+            target,
+            no_lock,
+            wait,
+            service,
+            command,
+        } => job::run_job(global_config, target, service, command, !no_lock, *wait),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::read_configuration;
+    use crate::pipeline::StepStatus;
+    use rstest::fixture;
+    use rstest::rstest;
+    use std::collections::BTreeMap;
+
+    #[fixture]
+    fn simple_config() -> Configuration {
+        Configuration {
+            path: "tests/01_trivial-backend-no-storage".to_string(),
+            docker_compose_file: "docker-compose.yaml".to_string(),
+            environments: vec![],
+            providers: BTreeMap::new(),
+        }
+    }
+
+    #[fixture]
+    fn simple_docker_compose() -> DockerComposeFormat {
+        DockerComposeFormat {
+            version: Some("3.7".to_string()),
+            services: Mapping::new(),
+        }
+    }
+
+    #[rstest]
+    fn test_no_config_file() {
+        let error = read_configuration(&PathBuf::from("not-a-file")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No config file found in not-a-file (No such file or directory (os error 2))."
+        );
+        assert_eq!(
+            crate::error::exit_code(error.as_ref()),
+            crate::error::EXIT_CONFIG
+        );
+    }
+
+    // does not work on pre-commit
+    // #[rstest]
+    // fn test_build(simple_config: Configuration, simple_docker_compose: DockerComposeFormat) {
+    //     let _ = build(
+    //         &simple_config,
+    //         &simple_docker_compose,
+    //         &vec![],
+    //         &vec!["BACKEND_TAG=0.0.1".to_owned()],
+    //     );
+    // }
+
+    #[rstest]
+    fn test_build_no_docker_compose(
+        mut simple_config: Configuration,
+        simple_docker_compose: DockerComposeFormat,
+    ) {
+        simple_config.docker_compose_file = "not-a-file".to_string();
+        assert!(build(
+            &simple_config,
+            &simple_docker_compose,
+            &BuildOptions::default()
+        )
+        .is_err());
+    }
+
+    #[rstest]
+    fn test_build_push_deploy_from(
+        simple_config: Configuration,
+        simple_docker_compose: DockerComposeFormat,
+    ) {
+        let provider = DockerProvider {
+            config: &simple_config,
+            format: simple_docker_compose,
+        };
+        let options = DeployOptions::default();
+        let pipeline = provider.build_push_deploy_pipeline(&options);
+        assert_eq!(pipeline.names(), ["build", "push", "deploy"]);
+
+        // deploy fails on config without environments before running any command
+        let (reports, result) = pipeline.run(Some("deploy"));
+        let statuses: Vec<(&str, StepStatus)> = reports
+            .iter()
+            .map(|report| (report.name, report.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("build", StepStatus::Skipped),
+                ("push", StepStatus::Skipped),
+                ("deploy", StepStatus::Failed),
+            ]
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Step `deploy` failed: No environments found in config."
+        );
+    }
+
+    #[rstest]
+    fn test_login_credentials() {
+        let file = Some(PathBuf::from("docker.json"));
+        let username = Some("deploy".to_owned());
+        let password = Some("secret".to_owned());
+        assert!(matches!(
+            login_credentials(&file, &None, &None, false).unwrap(),
+            LoginCredentials::DockerConfig(path) if path == Path::new("docker.json")
+        ));
+        assert!(matches!(
+            login_credentials(&None, &username, &password, false).unwrap(),
+            LoginCredentials::Password { username, password }
+                if username == "deploy" && password == "secret"
+        ));
+
+        let error = login_credentials(&file, &username, &None, false).unwrap_err();
+        assert_eq!(error.to_string(), "Username is conflicting with -f option.");
+        assert_eq!(
+            crate::error::exit_code(error.as_ref()),
+            crate::error::EXIT_INPUT
+        );
+        assert!(login_credentials(&None, &username, &password, true).is_err());
+    }
+
+    #[rstest]
+    fn test_remote_compose_command() {
+        let config = read_configuration(&PathBuf::from(
+            "tests/01_trivial-backend-no-storage/opday.toml",
+        ))
+        .unwrap();
+        assert_eq!(
+            remote_compose_command(&config, &config.environments[0]).to_string(),
+            "docker compose --env-file /root/test-01/.opday-generated/run.env \
+            -f /root/test-01/docker-compose.yaml \
+            -f /root/test-01/docker-compose.override-run.prod.yaml \
+            -f /root/test-01/.opday-generated/docker-compose.override-run.yaml"
+        );
+
+        let mut scope = config.environments[0].clone();
+        scope.export_path = "/srv/my app/$(id)".to_owned();
+        let command = remote_compose_command(&config, &scope)
+            .args(["up", "-d", "it's"])
+            .to_string();
+        assert!(
+            command.starts_with(
+                "docker compose --env-file '/srv/my app/$(id)/.opday-generated/run.env' "
+            ),
+            "{}",
+            command
+        );
+        assert!(command.ends_with(r#" up -d 'it'\''s'"#), "{}", command);
+    }
+
+    #[rstest]
+    fn test_record_release_command() {
+        let config = read_configuration(&PathBuf::from(
+            "tests/01_trivial-backend-no-storage/opday.toml",
+        ))
+        .unwrap();
+        let mut scope = config.environments[0].clone();
+        let export_path =
+            std::env::temp_dir().join(format!("opday-history-{}", std::process::id()));
+        scope.export_path = export_path.to_string_lossy().into_owned();
+        let mut release = Release::new("job", "prod", &[r#"GREETING=a\nb "c" \\d"#.to_owned()]);
+        release.command = Some(r#"printf 'x\ty\n'"#.to_owned());
+
+        // `sh` is dash on Debian, its `echo` would expand backslashes
+        let command = record_release_command(&scope, &release);
+        crate::exec::execute_short_command("sh", vec!["-c", &command], &[]).unwrap();
+        let history = fs::read_to_string(export_path.join(STATE_DIR).join(HISTORY_FILE)).unwrap();
+        fs::remove_dir_all(&export_path).unwrap();
+        assert_eq!(history.lines().count(), 1);
+        assert_eq!(serde_json::from_str::<Release>(&history).unwrap(), release);
+    }
+
+    #[rstest]
+    fn test_generated_run_file() {
+        let config = read_configuration(&PathBuf::from(
+            "tests/01_trivial-backend-no-storage/opday.toml",
+        ))
+        .unwrap();
+        let mut scope = config.environments[0].clone();
+        assert_eq!(generated_run_file(&scope), GENERATED_RUN_FILE);
+        assert!(placed_services_args(&scope, &["backend"]).is_empty());
+
+        scope.placement = [("db".to_owned(), vec!["postgres".to_owned()])].into();
+        let host_scope = scope.for_host(&scope.hosts[0]);
+        assert_eq!(
+            generated_run_file(&host_scope),
+            format!(
+                "docker-compose.override-run.{}.yaml",
+                scope.hosts[0].address.replace('@', "_")
+            )
+        );
+        assert_eq!(
+            placed_services_args(&host_scope, &["backend", "nginx"]),
+            ["backend", "nginx"]
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::thread;
+
+use clap::Args;
+
+use crate::config::{Configuration, Host, Scope};
+use crate::error::OpdayError;
+use crate::exec::{execute_command_lines, execute_interactive_command, RemoteHostCall};
+use crate::output;
+use crate::ui;
+
+use super::remote_compose_command;
+
+/// Where to run compose commands on already deployed environment.
+#[derive(Args)]
+pub struct RemoteTarget {
+    /// Path to config file
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Environment name
+    #[arg(short = 'e', long = "env", value_name = "NAME", env = "OPDAY_ENV")]
+    pub environment: Option<String>,
+
+    /// Run only on this host (all hosts of environment by default)
+    #[arg(long = "host", value_name = "ADDRESS")]
+    pub host: Option<String>,
+}
+
+pub(super) fn select_hosts<'a>(
+    scope: &'a Scope,
+    host: &Option<String>,
+) -> Result<Vec<&'a Host>, Box<dyn std::error::Error>> {
+    match host {
+        Some(address) => match scope.hosts.iter().find(|host| &host.address == address) {
+            Some(host) => Ok(vec![host]),
+            None => Err(Box::new(OpdayError::Input(format!(
+                "Host `{}` is not found in `{}` environment.",
+                address, scope.name
+            )))),
+        },
+        None => Ok(scope.hosts.iter().collect()),
+    }
+}
+
+pub(super) fn target_scope<'a>(
+    config: &'a Configuration,
+    target: &RemoteTarget,
+) -> Result<&'a Scope, Box<dyn std::error::Error>> {
+    Ok(config.required_environment(target.environment.as_deref())?)
+}
+
+/// Runs `docker compose <args>` on every host in parallel. Output lines are prefixed by host.
+fn run_on_hosts(
+    config: &Configuration,
+    scope: &Scope,
+    hosts: &[&Host],
+    args: &[&str],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let width = hosts.iter().map(|h| h.address.len()).max().unwrap_or(0);
+    let text = output::is_text();
+
+    let failures: Vec<(String, String)> = thread::scope(|s| {
+        let handles: Vec<_> = hosts
+            .iter()
+            .map(|host| {
+                s.spawn(move || {
+                    let address = &host.address;
+                    let host_scope = scope.for_host(host);
+                    let command = remote_compose_command(config, &host_scope)
+                        .args(args)
+                        .to_string();
+                    let params =
+                        RemoteHostCall::new(&host_scope).ssh_args(address, &command, false);
+                    let params2: Vec<&str> = params.iter().map(|s| s.as_str()).collect();
+                    execute_command_lines("ssh", params2, &|line| match text {
+                        true => {
+                            ui::println(&format!("{:width$} | {}", address, line, width = width))
+                        }
+                        false => output::line(Some(address), line),
+                    })
+                    .map_err(|e| (address.clone(), e.to_string()))
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().unwrap().err())
+            .collect()
+    });
+
+    if !failures.is_empty() {
+        let (hosts, messages): (Vec<String>, Vec<String>) = failures.into_iter().unzip();
+        return Err(Box::new(OpdayError::Remote {
+            hosts,
+            message: messages.join("\n"),
+        }));
+    }
+    Ok(())
+}
+
+/// Runs `docker compose <args>` with deploy's compose files on target hosts.
+pub fn compose(
+    config: &Configuration,
+    target: &RemoteTarget,
+    args: &[&str],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let scope = target_scope(config, target)?;
+    let hosts = select_hosts(scope, &target.host)?;
+    run_on_hosts(config, scope, &hosts, args)
+}
+
+/// Runs `docker compose exec` or `docker compose run --rm`. With a single host and
+/// a terminal the command is interactive, otherwise output is collected from all hosts.
+pub fn compose_exec(
+    config: &Configuration,
+    target: &RemoteTarget,
+    subcommand: &str,
+    service: &str,
+    command: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let scope = target_scope(config, target)?;
+    let hosts = select_hosts(scope, &target.host)?;
+    let interactive = hosts.len() == 1 && io::stdin().is_terminal();
+
+    let mut args: Vec<&str> = vec![subcommand];
+    if subcommand == "run" {
+        args.push("--rm");
+    }
+    if !interactive {
+        args.push("-T");
+    }
+    args.push(service);
+    args.extend(command.iter().map(|s| s.as_str()));
+
+    if interactive {
+        let host_scope = scope.for_host(hosts[0]);
+        let remote_command = remote_compose_command(config, &host_scope)
+            .args(&args)
+            .to_string();
+        let params =
+            RemoteHostCall::new(&host_scope).ssh_args(&hosts[0].address, &remote_command, true);
+        let params2: Vec<&str> = params.iter().map(|s| s.as_str()).collect();
+        return execute_interactive_command("ssh", params2);
+    }
+    run_on_hosts(config, scope, &hosts, &args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::read_configuration_raw;
+
+    #[test]
+    fn test_select_hosts() {
+        let config = read_configuration_raw(
+            r#"
+            [environments.prod]
+            hosts = ["root@one", "root@two"]
+            export_path = "/root/app"
+            registry = "registry"
+            registry_auth_config = "auth"
+            registry_export_auth_config = "export_auth"
+            docker_compose_overrides = []
+            "#,
+        )
+        .unwrap();
+        let scope = &config.environments[0];
+
+        assert_eq!(select_hosts(scope, &None).unwrap().len(), 2);
+        assert_eq!(
+            select_hosts(scope, &Some("root@two".to_owned())).unwrap()[0].address,
+            "root@two"
+        );
+        assert!(select_hosts(scope, &Some("root@three".to_owned())).is_err());
+    }
+}
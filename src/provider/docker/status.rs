@@ -0,0 +1,554 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::Path;
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Configuration, Scope};
+use crate::error::OpdayError;
+use crate::exec::{execute_short_command, shell_quote, RemoteHostCall};
+use crate::output::{self, Event};
+use crate::release::{Release, RELEASE_FILE};
+
+use super::{remote_compose_command, GENERATED_DIR};
+
+extern crate term;
+
+const SECTION_MARKER: &str = "@@opday:";
+
+/// Line of `docker compose ps --format json`.
+#[derive(Debug, Default, Deserialize)]
+struct ComposePsEntry {
+    #[serde(rename = "Name", default)]
+    name: String,
+    #[serde(rename = "Service", default)]
+    service: String,
+    #[serde(rename = "State", default)]
+    state: String,
+    #[serde(rename = "Health", default)]
+    health: String,
+    #[serde(rename = "Image", default)]
+    image: String,
+    #[serde(rename = "Status", default)]
+    status: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServiceStatus {
+    pub service: String,
+    pub container: String,
+    pub state: String,
+    pub health: String,
+    pub uptime: String,
+    pub restarts: Option<u32>,
+    pub image: String,
+    pub digests: Vec<String>,
+    pub expected_image: Option<String>,
+    pub drift: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HostStatus {
+    pub environment: String,
+    pub host: String,
+    pub release: Option<Release>,
+    pub services: Vec<ServiceStatus>,
+    pub error: Option<String>,
+}
+
+/// Single remote call printing release, containers and images in marked sections.
+fn remote_status_command(config: &Configuration, scope: &Scope) -> String {
+    let compose = remote_compose_command(config, scope);
+    let release_path = Path::new(&scope.export_path)
+        .join(GENERATED_DIR)
+        .join(RELEASE_FILE);
+    format!(
+        "echo {m}release; cat {release} 2>/dev/null; \
+         echo {m}ps; {compose} ps --all --format json; \
+         echo {m}inspect; {compose} ps --all --quiet | xargs -r docker inspect --format '{{{{.Name}}}}|{{{{.RestartCount}}}}|{{{{.Image}}}}'; \
+         echo {m}images; {compose} ps --all --quiet | xargs -r docker inspect --format '{{{{.Image}}}}' | sort -u | xargs -r docker image inspect --format '{{{{.Id}}}}|{{{{join .RepoDigests \",\"}}}}'; \
+         true",
+        m = SECTION_MARKER,
+        release = shell_quote(&release_path.to_string_lossy()),
+        compose = compose,
+    )
+}
+
+fn split_sections(output: &str) -> BTreeMap<String, String> {
+    let mut sections: BTreeMap<String, String> = BTreeMap::new();
+    let mut current: Option<String> = None;
+    for line in output.lines() {
+        if let Some(name) = line.strip_prefix(SECTION_MARKER) {
+            current = Some(name.trim().to_owned());
+            sections.entry(name.trim().to_owned()).or_default();
+            continue;
+        }
+        if let Some(name) = &current {
+            let section = sections.entry(name.clone()).or_default();
+            section.push_str(line);
+            section.push('\n');
+        }
+    }
+    sections
+}
+
+/// Compose prints JSON array in older versions and one object per line since 2.21.
+fn parse_compose_ps(output: &str) -> Vec<ComposePsEntry> {
+    let output = output.trim();
+    if output.starts_with('[') {
+        return serde_json::from_str(output).unwrap_or_default();
+    }
+    output
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Pairs of `name|value|...` lines keyed by first field.
+fn parse_pipe_lines(output: &str) -> BTreeMap<String, Vec<String>> {
+    output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut parts = line.split('|').map(|s| s.trim().to_owned());
+            let key = parts.next().unwrap_or_default();
+            (key.trim_start_matches('/').to_owned(), parts.collect())
+        })
+        .collect()
+}
+
+fn parse_host_status(scope: &Scope, address: &str, output: &str) -> HostStatus {
+    let sections = split_sections(output);
+    let section = |name: &str| sections.get(name).map(|s| s.as_str()).unwrap_or("");
+
+    let release = serde_json::from_str::<Release>(section("release")).ok();
+    let inspect = parse_pipe_lines(section("inspect"));
+    let images = parse_pipe_lines(section("images"));
+
+    let services = parse_compose_ps(section("ps"))
+        .into_iter()
+        .map(|entry| {
+            let container = inspect.get(&entry.name);
+            let restarts = container
+                .and_then(|fields| fields.first())
+                .and_then(|count| count.parse().ok());
+            let digests = container
+                .and_then(|fields| fields.get(1))
+                .and_then(|image_id| images.get(image_id))
+                .and_then(|fields| fields.first())
+                .map(|digests| {
+                    digests
+                        .split(',')
+                        .filter(|d| !d.is_empty())
+                        .map(|d| d.to_owned())
+                        .collect()
+                })
+                .unwrap_or_default();
+            ServiceStatus {
+                service: entry.service,
+                container: entry.name,
+                state: entry.state,
+                health: entry.health,
+                uptime: entry.status,
+                restarts,
+                image: entry.image,
+                digests,
+                expected_image: None,
+                drift: vec![],
+            }
+        })
+        .collect();
+
+    HostStatus {
+        environment: scope.name.clone(),
+        host: address.to_owned(),
+        release,
+        services,
+        error: None,
+    }
+}
+
+/// Images of services as resolved by local compose files with given variables.
+pub(crate) fn expected_images(
+    config: &Configuration,
+    scope: &Scope,
+    build_args: &[String],
+) -> BTreeMap<String, String> {
+    let mut args: Vec<String> = vec!["compose".to_owned(), "-f".to_owned()];
+    args.push(
+        Path::new(&config.path)
+            .join(&config.docker_compose_file)
+            .to_string_lossy()
+            .into_owned(),
+    );
+    for override_file in &scope.docker_compose_overrides {
+        args.push("-f".to_owned());
+        args.push(
+            Path::new(&config.path)
+                .join(override_file)
+                .to_string_lossy()
+                .into_owned(),
+        );
+    }
+    args.extend(["config", "--format", "json"].map(|s| s.to_owned()));
+    let args2: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+
+    let output = match execute_short_command("docker", args2, build_args) {
+        Ok(output) => output,
+        Err(e) => {
+            debug!("Could not resolve local compose config: {}", e);
+            return BTreeMap::new();
+        }
+    };
+    parse_compose_config_images(&output)
+}
+
+fn parse_compose_config_images(output: &str) -> BTreeMap<String, String> {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(output) else {
+        return BTreeMap::new();
+    };
+    value["services"]
+        .as_object()
+        .map(|services| {
+            services
+                .iter()
+                .filter_map(|(name, service)| {
+                    service["image"]
+                        .as_str()
+                        .map(|image| (name.clone(), image.to_owned()))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Registry digests of local image, empty when image is not pulled or pushed.
+pub(crate) fn local_digests(image: &str) -> BTreeSet<String> {
+    execute_short_command(
+        "docker",
+        vec![
+            "image",
+            "inspect",
+            "--format",
+            "{{join .RepoDigests \",\"}}",
+            image,
+        ],
+        &[],
+    )
+    .map(|output| {
+        output
+            .trim()
+            .split(',')
+            .filter(|d| !d.is_empty())
+            .map(|d| d.to_owned())
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+fn mark_drift(status: &mut HostStatus, expected: &BTreeMap<String, String>) {
+    for service in status.services.iter_mut() {
+        service.expected_image = expected.get(&service.service).cloned();
+        if service.state != "running" {
+            service.drift.push("not running".to_owned());
+        }
+        let Some(expected_image) = &service.expected_image else {
+            continue;
+        };
+        if *expected_image != service.image {
+            service.drift.push("image".to_owned());
+            continue;
+        }
+        let expected_digests = local_digests(expected_image);
+        if !expected_digests.is_empty()
+            && !service.digests.is_empty()
+            && !service.digests.iter().any(|d| expected_digests.contains(d))
+        {
+            service.drift.push("digest".to_owned());
+        }
+    }
+}
+
+fn host_status(
+    config: &Configuration,
+    scope: &Scope,
+    address: &str,
+    build_arg: &[String],
+) -> HostStatus {
+    let host = RemoteHostCall::new(scope);
+    let command = remote_status_command(config, scope);
+    let params = host.ssh_args(address, &command, false);
+    let params2: Vec<&str> = params.iter().map(|s| s.as_str()).collect();
+
+    let mut status = match execute_short_command("ssh", params2, &[]) {
+        Ok(output) => parse_host_status(scope, address, &output),
+        Err(e) => {
+            return HostStatus {
+                environment: scope.name.clone(),
+                host: address.to_owned(),
+                release: None,
+                services: vec![],
+                error: Some(e.to_string()),
+            }
+        }
+    };
+
+    // Without explicit build args compare with variables the release was deployed with
+    let build_args = match (&status.release, build_arg.is_empty()) {
+        (Some(release), true) => release.build_args.clone(),
+        _ => scope.build_args_with(build_arg),
+    };
+    mark_drift(&mut status, &expected_images(config, scope, &build_args));
+    status
+}
+
+fn short_digest(digests: &[String]) -> String {
+    digests
+        .first()
+        .and_then(|digest| digest.split_once("@sha256:"))
+        .map(|(_, hash)| hash.chars().take(12).collect())
+        .unwrap_or_else(|| "-".to_owned())
+}
+
+fn print_table(statuses: &[HostStatus]) {
+    let header = [
+        "ENV", "HOST", "RELEASE", "SERVICE", "STATE", "HEALTH", "RESTARTS", "UPTIME", "IMAGE",
+        "DIGEST", "DRIFT",
+    ];
+    let mut rows: Vec<[String; 11]> = vec![];
+    for status in statuses {
+        let release = status
+            .release
+            .as_ref()
+            .map(|r| r.id.clone())
+            .unwrap_or_else(|| "-".to_owned());
+        if let Some(error) = &status.error {
+            rows.push([
+                status.environment.clone(),
+                status.host.clone(),
+                release,
+                "-".to_owned(),
+                "unreachable".to_owned(),
+                "-".to_owned(),
+                "-".to_owned(),
+                "-".to_owned(),
+                "-".to_owned(),
+                "-".to_owned(),
+                error.lines().next().unwrap_or("").to_owned(),
+            ]);
+            continue;
+        }
+        if status.services.is_empty() {
+            rows.push([
+                status.environment.clone(),
+                status.host.clone(),
+                release.clone(),
+                "-".to_owned(),
+                "not deployed".to_owned(),
+                "-".to_owned(),
+                "-".to_owned(),
+                "-".to_owned(),
+                "-".to_owned(),
+                "-".to_owned(),
+                "-".to_owned(),
+            ]);
+        }
+        for service in &status.services {
+            rows.push([
+                status.environment.clone(),
+                status.host.clone(),
+                release.clone(),
+                service.service.clone(),
+                service.state.clone(),
+                if service.health.is_empty() {
+                    "-".to_owned()
+                } else {
+                    service.health.clone()
+                },
+                service
+                    .restarts
+                    .map(|r| r.to_string())
+                    .unwrap_or_else(|| "-".to_owned()),
+                service.uptime.clone(),
+                service.image.clone(),
+                short_digest(&service.digests),
+                if service.drift.is_empty() {
+                    "-".to_owned()
+                } else {
+                    service.drift.join(", ")
+                },
+            ]);
+        }
+    }
+
+    let mut widths = header.map(|h| h.len());
+    for row in &rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.len());
+        }
+    }
+
+    let mut terminal = term::stdout();
+    let header_line: Vec<String> = header
+        .iter()
+        .enumerate()
+        .map(|(i, h)| format!("{:width$}", h, width = widths[i]))
+        .collect();
+    println!("{}", header_line.join("  ").trim_end());
+    for row in &rows {
+        for (i, cell) in row.iter().enumerate() {
+            let color = match (i, cell.as_str()) {
+                (4, "running") => Some(term::color::GREEN),
+                (4, _) => Some(term::color::RED),
+                (5, "unhealthy") => Some(term::color::RED),
+                (5, "healthy") => Some(term::color::GREEN),
+                (10, "-") => None,
+                (10, _) => Some(term::color::YELLOW),
+                _ => None,
+            };
+            let separator = if i + 1 < row.len() { "  " } else { "\n" };
+            let text = if i + 1 < row.len() {
+                format!("{:width$}", cell, width = widths[i])
+            } else {
+                cell.clone()
+            };
+            match (terminal.as_mut(), color) {
+                (Some(terminal), Some(color)) => {
+                    let _ = terminal.fg(color);
+                    let _ = write!(terminal, "{}", text);
+                    let _ = terminal.reset();
+                    let _ = write!(terminal, "{}", separator);
+                }
+                (Some(terminal), None) => {
+                    let _ = write!(terminal, "{}{}", text, separator);
+                }
+                (None, _) => print!("{}{}", text, separator),
+            }
+        }
+    }
+}
+
+pub fn status(
+    config: &Configuration,
+    environment: Option<&str>,
+    build_arg: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let scopes: Vec<&Scope> = match environment {
+        Some(_) => config
+            .environment(environment)
+            .map_err(OpdayError::Config)?
+            .into_iter()
+            .collect(),
+        None => config.environments.iter().collect(),
+    };
+
+    let statuses: Vec<HostStatus> = std::thread::scope(|s| {
+        let handles: Vec<_> = scopes
+            .iter()
+            .flat_map(|scope| {
+                scope
+                    .hosts
+                    .iter()
+                    .map(move |host| (scope.for_host(host), host.address.as_str()))
+            })
+            .map(|(scope, address)| {
+                s.spawn(move || host_status(config, &scope, address, build_arg))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    match output::is_text() {
+        true => print_table(&statuses),
+        false => output::emit(Event::Report {
+            kind: "status",
+            data: serde_json::to_value(&statuses)?,
+        }),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::read_configuration;
+
+    const REMOTE_OUTPUT: &str = r#"@@opday:release
+{"id":"20261019-103015","kind":"deploy","created_at":"2026-10-19T10:30:15Z","environment":"prod","user":"me","build_args":["BACKEND_TAG=0.0.4"]}
+@@opday:ps
+{"ID":"a1","Name":"app-backend-1","Service":"backend","State":"running","Health":"healthy","Image":"registry.example.com/backend:0.0.4","Status":"Up 3 hours (healthy)"}
+{"ID":"b2","Name":"app-nginx-1","Service":"nginx","State":"exited","Health":"","Image":"registry.example.com/nginx:0.0.1","Status":"Exited (1) 5 minutes ago"}
+@@opday:inspect
+/app-backend-1|2|sha256:111
+/app-nginx-1|0|sha256:222
+@@opday:images
+sha256:111|registry.example.com/backend@sha256:aaaaaaaaaaaaaaaaaaaa
+sha256:222|
+"#;
+
+    fn scope() -> Scope {
+        read_configuration(Path::new("tests/01_trivial-backend-no-storage/opday.toml"))
+            .unwrap()
+            .environments[0]
+            .clone()
+    }
+
+    #[test]
+    fn test_unknown_environment() {
+        let config =
+            read_configuration(Path::new("tests/01_trivial-backend-no-storage/opday.toml"))
+                .unwrap();
+        let error = status(&config, Some("missing"), &[]).unwrap_err();
+        assert_eq!(
+            crate::error::exit_code(error.as_ref()),
+            crate::error::EXIT_CONFIG
+        );
+    }
+
+    #[test]
+    fn test_parse_host_status() {
+        let status = parse_host_status(&scope(), "root@host", REMOTE_OUTPUT);
+        assert_eq!(status.release.unwrap().id, "20261019-103015");
+        assert_eq!(status.services.len(), 2);
+
+        let backend = &status.services[0];
+        assert_eq!(backend.service, "backend");
+        assert_eq!(backend.restarts, Some(2));
+        assert_eq!(backend.health, "healthy");
+        assert_eq!(
+            backend.digests,
+            vec!["registry.example.com/backend@sha256:aaaaaaaaaaaaaaaaaaaa"]
+        );
+        assert_eq!(short_digest(&backend.digests), "aaaaaaaaaaaa");
+
+        let nginx = &status.services[1];
+        assert_eq!(nginx.state, "exited");
+        assert!(nginx.digests.is_empty());
+    }
+
+    #[test]
+    fn test_parse_compose_ps_array() {
+        let entries =
+            parse_compose_ps(r#"[{"Name":"app-backend-1","Service":"backend","State":"running"}]"#);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].service, "backend");
+    }
+
+    #[test]
+    fn test_mark_drift() {
+        let mut status = parse_host_status(&scope(), "root@host", REMOTE_OUTPUT);
+        let expected = parse_compose_config_images(
+            r#"{"services": {
+                "backend": {"image": "registry.example.com/backend:0.0.5"},
+                "nginx": {"image": "registry.example.com/nginx:0.0.1"}
+            }}"#,
+        );
+        mark_drift(&mut status, &expected);
+        assert_eq!(status.services[0].drift, vec!["image"]);
+        assert_eq!(status.services[1].drift, vec!["not running"]);
+    }
+}
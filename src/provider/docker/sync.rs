@@ -0,0 +1,292 @@
+use std::fs;
+use std::path::Path;
+
+use clap::Args;
+
+use crate::config::{Configuration, Scope};
+use crate::exec::{execute_short_command_with, ExecOptions, RemoteHostCall};
+
+use super::{DOCKER_JSON_FILE, GENERATED_DIR};
+
+/// Excludes in `.dockerignore` syntax, relative to `path`.
+pub const IGNORE_FILE: &str = ".opdayignore";
+const FILTER_FILE: &str = "rsync-filter";
+
+/// How project files are uploaded to hosts on deploy.
+#[derive(Args, Debug, Default, Clone)]
+pub struct SyncOptions {
+    /// Remove files on hosts which were removed locally in synced directories
+    #[arg(long, action)]
+    pub delete: bool,
+
+    /// Compare files by checksum instead of size and modification time
+    #[arg(long, action)]
+    pub checksum: bool,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SyncStats {
+    pub files_transferred: u64,
+    pub transferred_size: u64,
+    pub bytes_sent: u64,
+}
+
+/// Converts `.opdayignore` lines to rsync filter rules.
+///
+/// In `.dockerignore` the last matching pattern wins and rsync uses the first one,
+/// so the order is reversed. Patterns are anchored to `path` unless they start with `**/`.
+fn ignore_rules(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .rev()
+        .map(|line| {
+            let (rule, pattern) = match line.strip_prefix('!') {
+                Some(pattern) => ("+", pattern),
+                None => ("-", line),
+            };
+            let pattern = match pattern.strip_prefix("**/") {
+                Some(pattern) => pattern.to_owned(),
+                None => "/".to_owned() + pattern.trim_start_matches('/'),
+            };
+            format!("{} {}", rule, pattern)
+        })
+        .collect()
+}
+
+/// Includes every file with its parent directories, directories with all content.
+fn include_rules(paths: &[(String, bool)]) -> Vec<String> {
+    let mut rules: Vec<String> = vec![];
+    for (path, is_dir) in paths {
+        let path = path.trim_matches('/');
+        let mut parent = String::new();
+        let parts: Vec<&str> = path.split('/').collect();
+        for part in &parts[..parts.len() - 1] {
+            parent += "/";
+            parent += part;
+            let rule = format!("+ {}/", parent);
+            if !rules.contains(&rule) {
+                rules.push(rule);
+            }
+        }
+        let rule = if *is_dir {
+            format!("+ /{}/***", path)
+        } else {
+            format!("+ /{}", path)
+        };
+        if !rules.contains(&rule) {
+            rules.push(rule);
+        }
+    }
+    rules
+}
+
+/// Files deploy needs on hosts: compose files, overrides, generated files and `sync_files`.
+fn synced_paths(config: &Configuration, scope: &Scope) -> Vec<(String, bool)> {
+    let mut paths = vec![(config.docker_compose_file.clone(), false)];
+    paths.extend(
+        scope
+            .docker_compose_overrides
+            .iter()
+            .map(|file| (file.clone(), false)),
+    );
+    paths.push((GENERATED_DIR.to_owned(), true));
+    for file in &scope.sync_files {
+        let is_dir = Path::new(&config.path).join(file).is_dir();
+        paths.push((file.clone(), is_dir));
+    }
+    paths
+}
+
+fn filter_rules(config: &Configuration, scope: &Scope) -> Vec<String> {
+    let ignore = fs::read_to_string(Path::new(&config.path).join(IGNORE_FILE)).unwrap_or_default();
+    // registry credentials of login never leave local machine with deploy
+    let mut rules = vec![format!("- /{}/{}", GENERATED_DIR, DOCKER_JSON_FILE)];
+    rules.extend(ignore_rules(&ignore));
+    rules.extend(include_rules(&synced_paths(config, scope)));
+    rules.push("- *".to_owned());
+    rules
+}
+
+fn parse_number(value: &str) -> u64 {
+    value
+        .split_whitespace()
+        .next()
+        .unwrap_or("")
+        .replace(',', "")
+        .parse()
+        .unwrap_or(0)
+}
+
+/// Reads summary printed by `rsync --stats`.
+fn parse_stats(output: &str) -> SyncStats {
+    let mut stats = SyncStats::default();
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        match key.trim() {
+            "Number of regular files transferred" | "Number of files transferred" => {
+                stats.files_transferred = parse_number(value)
+            }
+            "Total transferred file size" => stats.transferred_size = parse_number(value),
+            "Total bytes sent" => stats.bytes_sent = parse_number(value),
+            _ => {}
+        }
+    }
+    stats
+}
+
+fn rsync_args(
+    host: &RemoteHostCall,
+    filter_file: &Path,
+    source: &str,
+    destination: &str,
+    options: &SyncOptions,
+) -> Vec<String> {
+    let mut params: Vec<String> = vec![];
+    params.push("-e".to_owned());
+    params.push(host.rsync_shell());
+    params.push("-rlt".to_owned());
+    params.push("--stats".to_owned());
+    params.push(format!("--filter=merge {}", filter_file.to_string_lossy()));
+    if options.delete {
+        params.push("--delete".to_owned());
+    }
+    if options.checksum {
+        params.push("--checksum".to_owned());
+    }
+    params.push(source.to_owned());
+    params.push(destination.to_owned());
+    params
+}
+
+/// Uploads project files needed for deploy to `export_path` on host.
+pub fn sync(
+    config: &Configuration,
+    scope: &Scope,
+    address: &str,
+    options: &SyncOptions,
+) -> Result<SyncStats, Box<dyn std::error::Error>> {
+    let host = RemoteHostCall::new(scope);
+    let filter_file = Path::new(&config.path)
+        .join(GENERATED_DIR)
+        .join(FILTER_FILE);
+    fs::write(&filter_file, filter_rules(config, scope).join("\n") + "\n")?;
+
+    let source = Path::new(&config.path).join("");
+    let destination = address.to_owned() + ":" + &scope.export_path;
+    let params = rsync_args(
+        &host,
+        &filter_file,
+        &source.to_string_lossy(),
+        &destination,
+        options,
+    );
+    let params2: Vec<&str> = params.iter().map(|s| s.as_str()).collect();
+    let (output, _) = execute_short_command_with("rsync", params2, &[], &ExecOptions::new(scope))?;
+    Ok(parse_stats(&output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore_rules() {
+        assert_eq!(
+            ignore_rules("# comment\nnode_modules\n**/*.pyc\n\n*.log\n!keep.log\n"),
+            vec!["+ /keep.log", "- /*.log", "- *.pyc", "- /node_modules"]
+        );
+    }
+
+    #[test]
+    fn test_include_rules() {
+        assert_eq!(
+            include_rules(&[
+                ("docker-compose.yaml".to_owned(), false),
+                ("config/nginx/site.conf".to_owned(), false),
+                ("config/nginx/certs".to_owned(), true),
+            ]),
+            vec![
+                "+ /docker-compose.yaml",
+                "+ /config/",
+                "+ /config/nginx/",
+                "+ /config/nginx/site.conf",
+                "+ /config/nginx/certs/***",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_filter_rules() {
+        let config = crate::config::read_configuration(Path::new(
+            "tests/01_trivial-backend-no-storage/opday.toml",
+        ))
+        .unwrap();
+        let rules = filter_rules(&config, &config.environments[0]);
+        let excluded = rules
+            .iter()
+            .position(|rule| rule == "- /.opday-generated/docker.json")
+            .unwrap();
+        let included = rules
+            .iter()
+            .position(|rule| rule == "+ /.opday-generated/***")
+            .unwrap();
+        assert!(excluded < included, "{:?}", rules);
+        assert_eq!(rules.last().unwrap(), "- *");
+    }
+
+    #[test]
+    fn test_parse_stats() {
+        let output = "
+Number of files: 5 (reg: 3, dir: 2)
+Number of created files: 1 (reg: 1)
+Number of regular files transferred: 2
+Total file size: 1,234 bytes
+Total transferred file size: 1,024 bytes
+Total bytes sent: 1,517
+Total bytes received: 54
+";
+        assert_eq!(
+            parse_stats(output),
+            SyncStats {
+                files_transferred: 2,
+                transferred_size: 1024,
+                bytes_sent: 1517,
+            }
+        );
+    }
+
+    #[test]
+    fn test_rsync_args() {
+        let host = RemoteHostCall {
+            private_key: Some("~/.ssh/key".to_owned()),
+            ..Default::default()
+        };
+        let options = SyncOptions {
+            delete: true,
+            checksum: false,
+        };
+        assert_eq!(
+            rsync_args(
+                &host,
+                Path::new(".opday-generated/rsync-filter"),
+                "./",
+                "root@host:/root/app",
+                &options
+            ),
+            vec![
+                "-e",
+                "ssh '-i' '~/.ssh/key'",
+                "-rlt",
+                "--stats",
+                "--filter=merge .opday-generated/rsync-filter",
+                "--delete",
+                "./",
+                "root@host:/root/app",
+            ]
+        );
+    }
+}
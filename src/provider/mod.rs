@@ -1 +1,130 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+use clap::{ArgMatches, Command};
+
+use crate::config::Configuration;
+use crate::error::OpdayError;
+
 pub mod docker;
+
+/// Tool managed by opday, e.g. docker compose services. Every provider is CLI subcommand
+/// with the same name and may have own `[providers.<name>]` config section.
+pub trait Provider: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Subcommand with own arguments, named after the provider.
+    fn command(&self) -> Command;
+
+    /// Config file passed to subcommand, it wins over the root `--config`.
+    fn config_file(&self, _matches: &ArgMatches) -> Option<PathBuf> {
+        None
+    }
+
+    /// Validates `[providers.<name>]` section of config.
+    fn check_config(&self, _section: &toml::Value) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Runs subcommand parsed into `matches`.
+    fn run(&self, config: &Configuration, matches: &ArgMatches) -> Result<(), Box<dyn Error>>;
+
+    /// Shows what `apply` would change in environment. Does nothing by default.
+    fn plan(
+        &self,
+        _config: &Configuration,
+        _environment: Option<&str>,
+        _build_args: &[String],
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Brings environment to the state described by config. Does nothing by default.
+    fn apply(
+        &self,
+        _config: &Configuration,
+        _environment: Option<&str>,
+        _build_args: &[String],
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Shows current state of environment. Does nothing by default.
+    fn status(
+        &self,
+        _config: &Configuration,
+        _environment: Option<&str>,
+        _build_args: &[String],
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+/// Registry of providers, a new provider module is added here.
+static PROVIDERS: &[&dyn Provider] = &[&docker::Docker];
+
+pub fn providers() -> &'static [&'static dyn Provider] {
+    PROVIDERS
+}
+
+pub fn find(name: &str) -> Option<&'static dyn Provider> {
+    PROVIDERS
+        .iter()
+        .copied()
+        .find(|provider| provider.name() == name)
+}
+
+/// Every provider section of config must belong to a known provider and pass its checks.
+pub fn check_config(config: &Configuration) -> Result<(), OpdayError> {
+    for (name, section) in &config.providers {
+        let Some(provider) = find(name) else {
+            let known: Vec<String> = PROVIDERS
+                .iter()
+                .map(|provider| format!("`{}`", provider.name()))
+                .collect();
+            return Err(OpdayError::Config(format!(
+                "Unknown provider `{}` in `providers`. Known providers: {}.",
+                name,
+                known.join(", ")
+            )));
+        };
+        provider
+            .check_config(section)
+            .map_err(|e| OpdayError::Config(format!("in `providers.{}`: {}", name, e)))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::read_configuration_raw;
+
+    #[test]
+    fn test_check_config() {
+        let config = read_configuration_raw("[providers.docker]\n").unwrap();
+        assert!(config.providers.contains_key("docker"));
+        assert!(check_config(&config).is_ok());
+
+        let config = read_configuration_raw("[providers.postgres]\nversion = 16\n").unwrap();
+        assert_eq!(
+            check_config(&config).unwrap_err().to_string(),
+            "Unknown provider `postgres` in `providers`. Known providers: `docker`."
+        );
+
+        let config = read_configuration_raw("[providers.docker]\nregistry = 1\n").unwrap();
+        assert!(check_config(&config)
+            .unwrap_err()
+            .to_string()
+            .starts_with("in `providers.docker`: "));
+    }
+
+    #[test]
+    fn test_registry() {
+        assert!(find("docker").is_some());
+        assert!(find("s3").is_none());
+        for provider in providers() {
+            assert_eq!(provider.command().get_name(), provider.name());
+        }
+    }
+}
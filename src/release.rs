@@ -0,0 +1,105 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// Directory under `export_path` with state kept between deploys.
+pub const STATE_DIR: &str = ".opday";
+/// Release history on remote host, one JSON record per line.
+pub const HISTORY_FILE: &str = "releases.jsonl";
+/// Current release description, exported together with generated files.
+pub const RELEASE_FILE: &str = "release.json";
+
+/// Deployed release or one-off job recorded on remote hosts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Release {
+    pub id: String,
+    pub kind: String,
+    pub created_at: String,
+    pub environment: String,
+    pub user: String,
+    #[serde(default)]
+    pub build_args: Vec<String>,
+    /// Command of one-off job
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Outcome of one-off job, `succeeded` or `failed`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+}
+
+impl Release {
+    pub fn new(kind: &str, environment: &str, build_args: &[String]) -> Release {
+        let (date, time) = utc_date_time(unix_now());
+        Release {
+            id: format!(
+                "{:04}{:02}{:02}-{:02}{:02}{:02}",
+                date.0, date.1, date.2, time.0, time.1, time.2
+            ),
+            kind: kind.to_owned(),
+            created_at: utc_now(),
+            environment: environment.to_owned(),
+            user: std::env::var("USER").unwrap_or_else(|_| "unknown".to_owned()),
+            build_args: build_args.to_vec(),
+            command: None,
+            status: None,
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Current time in RFC 3339 form, e.g. `2026-10-19T10:30:15Z`.
+pub fn utc_now() -> String {
+    let (date, time) = utc_date_time(unix_now());
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        date.0, date.1, date.2, time.0, time.1, time.2
+    )
+}
+
+/// Splits unix time into UTC `(year, month, day)` and `(hour, minute, second)`.
+fn utc_date_time(unix_seconds: u64) -> ((i64, u32, u32), (u32, u32, u32)) {
+    let days = (unix_seconds / 86400) as i64;
+    let seconds = (unix_seconds % 86400) as u32;
+
+    // Howard Hinnant's civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (
+        (year, month, day),
+        (seconds / 3600, seconds % 3600 / 60, seconds % 60),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utc_date_time() {
+        assert_eq!(utc_date_time(0), ((1970, 1, 1), (0, 0, 0)));
+        assert_eq!(utc_date_time(951782400), ((2000, 2, 29), (0, 0, 0)));
+        assert_eq!(utc_date_time(1792405815), ((2026, 10, 19), (10, 30, 15)));
+    }
+
+    #[test]
+    fn test_release_roundtrip() {
+        let release = Release::new("deploy", "prod", &["BACKEND_TAG=0.0.4".to_owned()]);
+        assert_eq!(release.id.len(), "20261019-103015".len());
+        let json = serde_json::to_string(&release).unwrap();
+        assert_eq!(serde_json::from_str::<Release>(&json).unwrap(), release);
+    }
+}
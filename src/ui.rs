@@ -0,0 +1,352 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+
+/// Last lines of command output shown under running task.
+const TAIL_LINES: usize = 5;
+const TICK: Duration = Duration::from_millis(100);
+const SPINNER: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+const DEFAULT_WIDTH: usize = 80;
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+static WIDTH: OnceLock<usize> = OnceLock::new();
+static STATE: Mutex<State> = Mutex::new(State {
+    next_id: 0,
+    tasks: vec![],
+    drawn: 0,
+    tick: 0,
+    ticking: false,
+});
+
+thread_local! {
+    /// Running tasks of this thread, the last one receives command output.
+    static CURRENT: RefCell<Vec<usize>> = const { RefCell::new(vec![]) };
+}
+
+struct TaskState {
+    id: usize,
+    label: String,
+    depth: usize,
+    started: Instant,
+    tail: VecDeque<String>,
+}
+
+/// Live block of running tasks redrawn below permanent output.
+struct State {
+    next_id: usize,
+    tasks: Vec<TaskState>,
+    /// Lines of live block currently on screen
+    drawn: usize,
+    tick: usize,
+    ticking: bool,
+}
+
+/// Live progress is drawn only for humans: text output on terminal without debug logs.
+pub fn init(enabled: bool) {
+    let enabled = enabled && io::stdout().is_terminal() && term::stdout().is_some();
+    let _ = ENABLED.set(enabled);
+    if enabled {
+        let _ = WIDTH.set(terminal_width());
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.get().copied().unwrap_or(false)
+}
+
+fn terminal_width() -> usize {
+    if let Some(columns) = std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok()) {
+        return columns;
+    }
+    // `stty` reads size of terminal from stdin
+    Command::new("stty")
+        .arg("size")
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .and_then(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .split_whitespace()
+                .nth(1)
+                .and_then(|columns| columns.parse().ok())
+        })
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+/// Removes ANSI escape sequences and control characters, so line takes one row.
+fn sanitize(line: &str) -> String {
+    let mut result = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                if chars.peek() == Some(&'[') {
+                    chars.next();
+                    for c in chars.by_ref() {
+                        if c.is_ascii_alphabetic() {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\t' => result.push(' '),
+            // progress bars redraw line after carriage return, the last state wins
+            '\r' => result.clear(),
+            c if c.is_control() => {}
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+fn truncate(line: &str, width: usize) -> String {
+    match line.char_indices().nth(width) {
+        Some((index, _)) => line[..index].to_owned(),
+        None => line.to_owned(),
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64();
+    match seconds < 60.0 {
+        true => format!("{:.1}s", seconds),
+        false => format!(
+            "{}m{:02}s",
+            duration.as_secs() / 60,
+            duration.as_secs() % 60
+        ),
+    }
+}
+
+fn clear(state: &mut State, terminal: &mut Box<term::StdoutTerminal>) {
+    for _ in 0..state.drawn {
+        let _ = terminal.cursor_up();
+        let _ = terminal.delete_line();
+    }
+    let _ = terminal.carriage_return();
+    state.drawn = 0;
+}
+
+fn render(state: &mut State, terminal: &mut Box<term::StdoutTerminal>) {
+    let width = WIDTH
+        .get()
+        .copied()
+        .unwrap_or(DEFAULT_WIDTH)
+        .saturating_sub(1);
+    let spinner = SPINNER[state.tick % SPINNER.len()];
+    let mut drawn = 0;
+    for task in &state.tasks {
+        let indent = "  ".repeat(task.depth);
+        let _ = write!(terminal, "{}", indent);
+        let _ = terminal.fg(term::color::CYAN);
+        let _ = write!(terminal, "{}", spinner);
+        let _ = terminal.reset();
+        let title = format!(
+            " {}  {}",
+            task.label,
+            format_duration(task.started.elapsed())
+        );
+        let _ = writeln!(
+            terminal,
+            "{}",
+            truncate(&title, width.saturating_sub(indent.len() + 1))
+        );
+        drawn += 1;
+        for line in &task.tail {
+            let line = format!("{}  {}", indent, line);
+            let _ = terminal.attr(term::Attr::Dim);
+            let _ = write!(terminal, "{}", truncate(&line, width));
+            let _ = terminal.reset();
+            let _ = writeln!(terminal);
+            drawn += 1;
+        }
+    }
+    let _ = terminal.flush();
+    state.drawn = drawn;
+}
+
+fn redraw(state: &mut State) {
+    if let Some(mut terminal) = term::stdout() {
+        clear(state, &mut terminal);
+        render(state, &mut terminal);
+    }
+}
+
+fn start_ticking(state: &mut State) {
+    if state.ticking {
+        return;
+    }
+    state.ticking = true;
+    thread::spawn(|| loop {
+        thread::sleep(TICK);
+        let mut state = STATE.lock().unwrap();
+        if state.tasks.is_empty() {
+            state.ticking = false;
+            return;
+        }
+        state.tick += 1;
+        redraw(&mut state);
+    });
+}
+
+fn start(label: &str) -> usize {
+    let depth = CURRENT.with(|current| current.borrow().len());
+    let mut state = STATE.lock().unwrap();
+    let id = state.next_id;
+    state.next_id += 1;
+    state.tasks.push(TaskState {
+        id,
+        label: label.to_owned(),
+        depth,
+        started: Instant::now(),
+        tail: VecDeque::new(),
+    });
+    redraw(&mut state);
+    start_ticking(&mut state);
+    id
+}
+
+/// Moves finished task from live block to permanent output. Failed task keeps its tail.
+fn finish(id: usize, succeeded: bool) {
+    let mut state = STATE.lock().unwrap();
+    let Some(position) = state.tasks.iter().position(|task| task.id == id) else {
+        return;
+    };
+    let task = state.tasks.remove(position);
+    let Some(mut terminal) = term::stdout() else {
+        return;
+    };
+    clear(&mut state, &mut terminal);
+    let indent = "  ".repeat(task.depth);
+    let _ = write!(terminal, "{}", indent);
+    let (mark, color) = match succeeded {
+        true => ('✓', term::color::GREEN),
+        false => ('✗', term::color::RED),
+    };
+    let _ = terminal.fg(color);
+    let _ = write!(terminal, "{}", mark);
+    let _ = terminal.reset();
+    let _ = writeln!(
+        terminal,
+        " {}  {}",
+        task.label,
+        format_duration(task.started.elapsed())
+    );
+    if !succeeded {
+        for line in &task.tail {
+            let _ = writeln!(terminal, "{}  {}", indent, line);
+        }
+    }
+    render(&mut state, &mut terminal);
+}
+
+/// Runs `run` as task shown with spinner and output tail until it finishes.
+/// Without terminal the label is logged as plain progress line.
+pub fn task<T, E>(label: &str, run: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+    if !is_enabled() {
+        info!("{}", label);
+        let started = Instant::now();
+        let result = run();
+        debug!(
+            "{} finished in {}",
+            label,
+            format_duration(started.elapsed())
+        );
+        return result;
+    }
+    let id = start(label);
+    CURRENT.with(|current| current.borrow_mut().push(id));
+    let result = run();
+    CURRENT.with(|current| current.borrow_mut().pop());
+    finish(id, result.is_ok());
+    result
+}
+
+/// Task of this thread receiving command output, to pass into threads reading the output.
+pub fn current() -> Option<usize> {
+    CURRENT.with(|current| current.borrow().last().copied())
+}
+
+/// Makes `task` current in this thread.
+pub fn adopt(task: Option<usize>) {
+    if let Some(task) = task {
+        CURRENT.with(|current| current.borrow_mut().push(task));
+    }
+}
+
+/// Adds line to tail of current task. Returns `false` when there is no running task.
+pub fn line(line: &str) -> bool {
+    let Some(id) = current() else {
+        return false;
+    };
+    let mut state = STATE.lock().unwrap();
+    let Some(task) = state.tasks.iter_mut().find(|task| task.id == id) else {
+        return false;
+    };
+    task.tail.push_back(sanitize(line));
+    if task.tail.len() > TAIL_LINES {
+        task.tail.pop_front();
+    }
+    true
+}
+
+/// Prints permanent line above live block.
+pub fn println(line: &str) {
+    if !is_enabled() {
+        println!("{}", line);
+        return;
+    }
+    let mut state = STATE.lock().unwrap();
+    let Some(mut terminal) = term::stdout() else {
+        return;
+    };
+    clear(&mut state, &mut terminal);
+    let _ = writeln!(terminal, "{}", line);
+    render(&mut state, &mut terminal);
+}
+
+/// Erases live block before other output, e.g. logs, it is drawn again on next tick.
+pub fn suspend() {
+    if !is_enabled() {
+        return;
+    }
+    let mut state = STATE.lock().unwrap();
+    if let Some(mut terminal) = term::stdout() {
+        clear(&mut state, &mut terminal);
+        let _ = terminal.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("\x1b[32m#5 DONE\x1b[0m 0.3s"), "#5 DONE 0.3s");
+        assert_eq!(sanitize("10%\r55%\r100%"), "100%");
+        assert_eq!(sanitize("a\tb\x07"), "a b");
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("⠋ building", 4), "⠋ bu");
+        assert_eq!(truncate("short", 10), "short");
+    }
+
+    #[test]
+    fn test_task_fallback() {
+        let result: Result<u32, String> = task("Building images", || {
+            assert!(!line("not shown"));
+            Ok(3)
+        });
+        assert_eq!(result, Ok(3));
+        assert_eq!(format_duration(Duration::from_secs(75)), "1m15s");
+    }
+}